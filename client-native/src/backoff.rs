@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Delay before first reconnection attempt.
+const INITIAL_DELAY: Duration = Duration::from_millis(500);
+
+/// Upper bound of delay between reconnection attempts.
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Exponential backoff with jitter.
+///
+/// Each delay is twice as long as the previous one (up to [MAX_DELAY]),
+/// randomized to range between half and full of that value, so that clients
+/// disconnected at the same time don't reconnect all at once.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    attempts: u32,
+}
+
+impl Backoff {
+    /// Get delay before next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = INITIAL_DELAY
+            .saturating_mul(2u32.saturating_pow(self.attempts))
+            .min(MAX_DELAY);
+        self.attempts = self.attempts.saturating_add(1);
+        rand::thread_rng().gen_range(delay / 2..=delay)
+    }
+
+    /// Start over after successful attempt.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backoff, INITIAL_DELAY, MAX_DELAY};
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::default();
+        let delay = backoff.next_delay();
+        assert!(delay >= INITIAL_DELAY / 2 && delay <= INITIAL_DELAY);
+        let delay = backoff.next_delay();
+        assert!(delay >= INITIAL_DELAY && delay <= INITIAL_DELAY * 2);
+
+        for _ in 0..100 {
+            assert!(backoff.next_delay() <= MAX_DELAY);
+        }
+        assert!(backoff.next_delay() >= MAX_DELAY / 2);
+
+        backoff.reset();
+        assert!(backoff.next_delay() <= INITIAL_DELAY);
+    }
+}
@@ -0,0 +1,507 @@
+use std::{
+    io::{self, stdout, Stdout, Write},
+    mem,
+};
+
+use crossterm::{
+    cursor,
+    event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    terminal::{self, Clear, ClearType},
+    QueueableCommand,
+};
+use futures::{
+    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    select, FutureExt, StreamExt,
+};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Maximum number of entered lines kept in history.
+const MAX_HISTORY_LENGTH: usize = 1000;
+
+/// Input received from terminal, see [Readline::next_input].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    /// Line was entered.
+    Line(String),
+
+    /// Text of line being edited changed, contains its new text.
+    Edited(String),
+
+    /// User pressed Ctrl-D.
+    Eof,
+
+    /// User pressed Ctrl-C.
+    Interrupted,
+}
+
+/// Writer printing lines above the line being edited.
+///
+/// Data is printed once a whole line is written (or writer is flushed),
+/// while [Readline::next_input] or [Readline::flush] is running.
+pub struct Output {
+    buffer: Vec<u8>,
+    sender: UnboundedSender<Vec<u8>>,
+}
+
+impl Output {
+    fn send(&self, data: Vec<u8>) -> io::Result<()> {
+        self.sender
+            .unbounded_send(data)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "terminal was closed"))
+    }
+}
+
+impl Clone for Output {
+    fn clone(&self) -> Self {
+        Output {
+            buffer: vec![],
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if let Some(end) = self.buffer.iter().rposition(|byte| *byte == b'\n') {
+            let rest = self.buffer.split_off(end + 1);
+            let lines = mem::replace(&mut self.buffer, rest);
+            self.send(lines)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let data = mem::take(&mut self.buffer);
+            self.send(data)?;
+        }
+        Ok(())
+    }
+}
+
+/// Line editor reading lines from terminal in raw mode
+/// while printing [Output] above the line being edited.
+///
+/// Supports moving cursor with Left, Right, Home, End (or Ctrl-A, Ctrl-E) and Ctrl-Left,
+/// Ctrl-Right (by words), browsing history with Up and Down, erasing input before cursor
+/// with Ctrl-U, previous word with Ctrl-W and clearing screen with Ctrl-L.
+pub struct Readline {
+    terminal: Stdout,
+    events: EventStream,
+    output: UnboundedReceiver<Vec<u8>>,
+    /// Kept so that output channel stays open when all [Output]s are dropped.
+    _sender: UnboundedSender<Vec<u8>>,
+    line: Line,
+}
+
+impl Readline {
+    /// Switch terminal to raw mode and show given prompt,
+    /// returns [Output] to print lines above it.
+    pub fn new(prompt: String) -> io::Result<(Readline, Output)> {
+        let (sender, output) = unbounded();
+        terminal::enable_raw_mode()?;
+        let mut readline = Readline {
+            terminal: stdout(),
+            events: EventStream::new(),
+            output,
+            _sender: sender.clone(),
+            line: Line::new(prompt, terminal::size()?.0),
+        };
+        readline.line.render(&mut readline.terminal)?;
+        readline.terminal.flush()?;
+        let output = Output {
+            buffer: vec![],
+            sender,
+        };
+        Ok((readline, output))
+    }
+
+    pub fn update_prompt(&mut self, prompt: &str) -> io::Result<()> {
+        self.line.update_prompt(prompt, &mut self.terminal)?;
+        self.terminal.flush()
+    }
+
+    /// Wait until user edits or enters a line, printing [Output] in the meantime.
+    pub async fn next_input(&mut self) -> io::Result<Input> {
+        loop {
+            select! {
+                event = self.events.next().fuse() => match event {
+                    Some(event) => {
+                        let input = self.line.handle_event(event?, &mut self.terminal)?;
+                        self.terminal.flush()?;
+                        if let Some(input) = input {
+                            return Ok(input);
+                        }
+                    }
+                    None => return Ok(Input::Eof),
+                },
+                data = self.output.select_next_some() => {
+                    self.line.print(&data, &mut self.terminal)?;
+                    self.terminal.flush()?;
+                },
+            }
+        }
+    }
+
+    /// Add entered line to history browsed with Up and Down keys.
+    pub fn add_history_entry(&mut self, entry: String) {
+        self.line.add_history_entry(entry);
+    }
+
+    /// Print all pending [Output] and erase the prompt.
+    pub fn flush(&mut self) -> io::Result<()> {
+        while let Ok(data) = self.output.try_recv() {
+            self.line.print(&data, &mut self.terminal)?;
+        }
+        self.line.clear(&mut self.terminal)?;
+        self.terminal.flush()
+    }
+}
+
+impl Drop for Readline {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Line being edited and its rendering on terminal.
+#[derive(Debug, Default)]
+struct Line {
+    prompt: String,
+    text: String,
+    /// Byte index of cursor in `text` (at grapheme boundary).
+    cursor: usize,
+    /// Width of terminal in columns.
+    width: usize,
+    /// Row terminal cursor is at, relative to the first row of the prompt.
+    row: usize,
+    history: Vec<String>,
+    /// Index of history entry being edited (`history.len()` if it's a new line).
+    history_index: usize,
+    /// New line being edited before browsing history.
+    draft: String,
+}
+
+impl Line {
+    fn new(prompt: String, width: u16) -> Self {
+        Line {
+            prompt,
+            width: width.max(1).into(),
+            ..Default::default()
+        }
+    }
+
+    /// Handle terminal event, returns `Some` if line was entered or edited.
+    fn handle_event(&mut self, event: Event, term: &mut impl Write) -> io::Result<Option<Input>> {
+        let text = self.text.clone();
+        let input = match event {
+            Event::Key(KeyEvent {
+                code,
+                modifiers: KeyModifiers::CONTROL,
+                kind: KeyEventKind::Press,
+                ..
+            }) => self.handle_control_key(code, term)?,
+            Event::Key(KeyEvent {
+                code,
+                kind: KeyEventKind::Press,
+                ..
+            }) => self.handle_key(code, term)?,
+            Event::Resize(width, _) => {
+                self.clear(term)?;
+                self.width = width.max(1).into();
+                self.render(term)?;
+                None
+            }
+            _ => None,
+        };
+        Ok(input.or_else(|| (self.text != text).then(|| Input::Edited(self.text.clone()))))
+    }
+
+    fn handle_control_key(
+        &mut self,
+        code: KeyCode,
+        term: &mut impl Write,
+    ) -> io::Result<Option<Input>> {
+        match code {
+            KeyCode::Char('d') => {
+                self.clear(term)?;
+                return Ok(Some(Input::Eof));
+            }
+            KeyCode::Char('c') => {
+                self.text.clear();
+                self.cursor = 0;
+                self.redraw(term)?;
+                return Ok(Some(Input::Interrupted));
+            }
+            KeyCode::Char('l') => {
+                term.queue(Clear(ClearType::All))?
+                    .queue(cursor::MoveTo(0, 0))?;
+                self.row = 0;
+                self.render(term)?;
+            }
+            KeyCode::Char('u') => {
+                self.text.drain(..self.cursor);
+                self.cursor = 0;
+                self.redraw(term)?;
+            }
+            KeyCode::Char('w') => {
+                let start = self.previous_word_start();
+                self.text.drain(start..self.cursor);
+                self.cursor = start;
+                self.redraw(term)?;
+            }
+            KeyCode::Char('a') => self.move_cursor(0, term)?,
+            KeyCode::Char('e') => self.move_cursor(self.text.len(), term)?,
+            KeyCode::Left => self.move_cursor(self.previous_word_start(), term)?,
+            KeyCode::Right => self.move_cursor(self.next_word_end(), term)?,
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn handle_key(&mut self, code: KeyCode, term: &mut impl Write) -> io::Result<Option<Input>> {
+        match code {
+            KeyCode::Enter => {
+                let entered = format!("{}{}\n", self.prompt, self.text);
+                let line = mem::take(&mut self.text);
+                self.cursor = 0;
+                self.history_index = self.history.len();
+                self.draft.clear();
+                self.print(entered.as_bytes(), term)?;
+                return Ok(Some(Input::Line(line)));
+            }
+            KeyCode::Char(character) => {
+                self.text.insert(self.cursor, character);
+                self.cursor += character.len_utf8();
+                self.redraw(term)?;
+            }
+            KeyCode::Backspace => {
+                let start = self.previous_grapheme();
+                self.text.drain(start..self.cursor);
+                self.cursor = start;
+                self.redraw(term)?;
+            }
+            KeyCode::Delete => {
+                let end = self.next_grapheme();
+                self.text.drain(self.cursor..end);
+                self.redraw(term)?;
+            }
+            KeyCode::Left => self.move_cursor(self.previous_grapheme(), term)?,
+            KeyCode::Right => self.move_cursor(self.next_grapheme(), term)?,
+            KeyCode::Home => self.move_cursor(0, term)?,
+            KeyCode::End => self.move_cursor(self.text.len(), term)?,
+            KeyCode::Up if self.history_index > 0 => {
+                if self.history_index == self.history.len() {
+                    self.draft = mem::take(&mut self.text);
+                }
+                self.history_index -= 1;
+                self.set_text(self.history[self.history_index].clone(), term)?;
+            }
+            KeyCode::Down if self.history_index < self.history.len() => {
+                self.history_index += 1;
+                let text = match self.history.get(self.history_index) {
+                    Some(entry) => entry.clone(),
+                    None => mem::take(&mut self.draft),
+                };
+                self.set_text(text, term)?;
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn add_history_entry(&mut self, entry: String) {
+        if self.history.last() != Some(&entry) {
+            self.history.push(entry);
+            if self.history.len() > MAX_HISTORY_LENGTH {
+                self.history.remove(0);
+            }
+        }
+        self.history_index = self.history.len();
+    }
+
+    fn update_prompt(&mut self, prompt: &str, term: &mut impl Write) -> io::Result<()> {
+        self.clear(term)?;
+        self.prompt = prompt.to_string();
+        self.render(term)
+    }
+
+    /// Print data above the line, incomplete last line of data is ended.
+    fn print(&mut self, data: &[u8], term: &mut impl Write) -> io::Result<()> {
+        self.clear(term)?;
+        // newlines don't return carriage in raw mode
+        for line in data.split_inclusive(|byte| *byte == b'\n') {
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            term.write_all(line)?;
+            term.write_all(b"\r\n")?;
+        }
+        self.render(term)
+    }
+
+    fn set_text(&mut self, text: String, term: &mut impl Write) -> io::Result<()> {
+        self.text = text;
+        self.cursor = self.text.len();
+        self.redraw(term)
+    }
+
+    fn previous_grapheme(&self) -> usize {
+        self.text[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(index, _)| index)
+    }
+
+    fn next_grapheme(&self) -> usize {
+        self.text[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map_or(self.cursor, |grapheme| self.cursor + grapheme.len())
+    }
+
+    fn previous_word_start(&self) -> usize {
+        let before = self.text[..self.cursor].trim_end_matches(' ');
+        before.rfind(' ').map_or(0, |index| index + 1)
+    }
+
+    fn next_word_end(&self) -> usize {
+        let after = &self.text[self.cursor..];
+        let rest = after.trim_start_matches(' ');
+        let start = self.cursor + after.len() - rest.len();
+        start + rest.find(' ').unwrap_or(rest.len())
+    }
+
+    /// Position (in columns from the start of prompt) of given byte index of text.
+    fn position(&self, index: usize) -> usize {
+        self.prompt.width() + self.text[..index].width()
+    }
+
+    /// Move terminal cursor to the first row of prompt and erase everything below.
+    fn clear(&mut self, term: &mut impl Write) -> io::Result<()> {
+        if self.row > 0 {
+            term.queue(cursor::MoveUp(self.row as u16))?;
+        }
+        term.queue(cursor::MoveToColumn(0))?
+            .queue(Clear(ClearType::FromCursorDown))?;
+        self.row = 0;
+        Ok(())
+    }
+
+    /// Write prompt and text, terminal cursor has to be at the start of the first row of prompt.
+    fn render(&mut self, term: &mut impl Write) -> io::Result<()> {
+        write!(term, "{}{}", self.prompt, self.text)?;
+        let end = self.position(self.text.len());
+        if end > 0 && end.is_multiple_of(self.width) {
+            // move from the end of full row, so that all rows of line exist
+            term.write_all(b"\r\n")?;
+        }
+        self.row = end / self.width;
+        self.move_cursor(self.cursor, term)
+    }
+
+    fn redraw(&mut self, term: &mut impl Write) -> io::Result<()> {
+        self.clear(term)?;
+        self.render(term)
+    }
+
+    /// Move cursor to given byte index of text.
+    fn move_cursor(&mut self, index: usize, term: &mut impl Write) -> io::Result<()> {
+        self.cursor = index;
+        let position = self.position(index);
+        let row = position / self.width;
+        if row < self.row {
+            term.queue(cursor::MoveUp((self.row - row) as u16))?;
+        } else if row > self.row {
+            term.queue(cursor::MoveDown((row - self.row) as u16))?;
+        }
+        term.queue(cursor::MoveToColumn((position % self.width) as u16))?;
+        self.row = row;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+
+    use super::{Input, Line};
+
+    fn key(line: &mut Line, code: KeyCode, modifiers: KeyModifiers) -> Option<Input> {
+        let event = Event::Key(KeyEvent::new(code, modifiers));
+        line.handle_event(event, &mut vec![]).unwrap()
+    }
+
+    fn type_text(line: &mut Line, text: &str) {
+        for character in text.chars() {
+            key(line, KeyCode::Char(character), KeyModifiers::NONE);
+        }
+    }
+
+    #[test]
+    fn test_line_editing() {
+        let mut line = Line::new("> ".to_string(), 10);
+        assert_eq!(
+            key(&mut line, KeyCode::Char('H'), KeyModifiers::SHIFT),
+            Some(Input::Edited("H".to_string()))
+        );
+        type_text(&mut line, "ello world");
+        assert_eq!(line.row, 1);
+
+        // moving cursor doesn't edit line
+        assert_eq!(key(&mut line, KeyCode::Left, KeyModifiers::CONTROL), None);
+        assert_eq!(key(&mut line, KeyCode::Left, KeyModifiers::NONE), None);
+        assert_eq!(line.row, 0);
+        assert_eq!(
+            key(&mut line, KeyCode::Backspace, KeyModifiers::NONE),
+            Some(Input::Edited("Hell world".to_string()))
+        );
+        assert_eq!(
+            key(&mut line, KeyCode::Char('w'), KeyModifiers::CONTROL),
+            Some(Input::Edited(" world".to_string()))
+        );
+        assert_eq!(key(&mut line, KeyCode::Backspace, KeyModifiers::NONE), None);
+        key(&mut line, KeyCode::End, KeyModifiers::NONE);
+        type_text(&mut line, " 👍\u{1F3FD}");
+        assert_eq!(
+            key(&mut line, KeyCode::Backspace, KeyModifiers::NONE),
+            Some(Input::Edited(" world ".to_string()))
+        );
+
+        assert_eq!(
+            key(&mut line, KeyCode::Enter, KeyModifiers::NONE),
+            Some(Input::Line(" world ".to_string()))
+        );
+        assert_eq!(line.text, "");
+        assert_eq!(
+            key(&mut line, KeyCode::Char('c'), KeyModifiers::CONTROL),
+            Some(Input::Interrupted)
+        );
+        assert_eq!(
+            key(&mut line, KeyCode::Char('d'), KeyModifiers::CONTROL),
+            Some(Input::Eof)
+        );
+    }
+
+    #[test]
+    fn test_line_history() {
+        let mut line = Line::new("> ".to_string(), 80);
+        line.add_history_entry("/help".to_string());
+        line.add_history_entry("/rooms".to_string());
+        type_text(&mut line, "Hi");
+
+        assert_eq!(
+            key(&mut line, KeyCode::Up, KeyModifiers::NONE),
+            Some(Input::Edited("/rooms".to_string()))
+        );
+        key(&mut line, KeyCode::Up, KeyModifiers::NONE);
+        assert_eq!(key(&mut line, KeyCode::Up, KeyModifiers::NONE), None);
+        assert_eq!(line.text, "/help");
+        key(&mut line, KeyCode::Down, KeyModifiers::NONE);
+        assert_eq!(
+            key(&mut line, KeyCode::Down, KeyModifiers::NONE),
+            Some(Input::Edited("Hi".to_string()))
+        );
+        assert_eq!(key(&mut line, KeyCode::Down, KeyModifiers::NONE), None);
+    }
+}
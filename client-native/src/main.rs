@@ -1,23 +1,53 @@
-use anyhow::Result;
+mod backoff;
+mod input;
+mod tls;
+
+use anyhow::{anyhow, bail, Result};
 use clap::Parser;
-use futures::{select, FutureExt, StreamExt};
+use futures::{
+    future, pin_mut, select,
+    stream::{self, select_all, BoxStream, SelectAll},
+    FutureExt, StreamExt,
+};
 use kodec::binary::Codec;
-use lazy_static::lazy_static;
 use mezzenger_websocket::Transport;
-use regex::{Captures, Regex};
-use rustyline_async::{Readline, ReadlineEvent, SharedWriter};
-use std::io::Write;
-use tokio::spawn;
-use tokio_tungstenite::connect_async;
+use std::{
+    fmt::Debug,
+    io::Write,
+    time::{Duration, Instant},
+};
+use tokio::{
+    spawn,
+    time::{interval, sleep},
+};
+use tokio_tungstenite::{
+    connect_async_tls_with_config,
+    tungstenite::{client::IntoClientRequest, http::header::AUTHORIZATION},
+};
 use url::Url;
 
-use common::api::chat::{Api, Consumer};
+use backoff::Backoff;
+use common::api::{
+    auth::{AuthError, Credentials, Token, LOGIN_PATH, REGISTER_PATH},
+    chat::{
+        self, format_presence, format_reactions_update, format_revision, format_user_names, Api,
+        ChatError, ChatMessage, Consumer, MessageEvent, PresenceStatus, ServerEvent, Session,
+        Typing, DEFAULT_ROOM, HEARTBEAT_INTERVAL, TYPING_REFRESH_INTERVAL,
+    },
+};
+use common::command::{Command, COMMANDS};
+use input::{Input, Output, Readline};
+use tls::{Clients, TlsArgs};
 use zzrpc::consumer::{Configuration, Consume};
 
-lazy_static! {
-    static ref FIBONACCI_PATTERN: Regex = Regex::new(r"^fibonacci\((0|[1-9][0-9]*)\)$").unwrap();
-    static ref FACTORIAL_PATTERN: Regex = Regex::new(r"^(0|[1-9][0-9]*)!$").unwrap();
-}
+/// Number of past messages displayed after joining a room.
+const HISTORY_LENGTH: usize = 20;
+
+/// How long session has to stay connected before reconnection backoff is reset.
+const STABLE_CONNECTION_TIME: Duration = Duration::from_secs(10);
+
+/// How many times resuming session still in use is retried before starting a new session.
+const MAX_SESSION_IN_USE_RETRIES: u32 = 5;
 
 /// Web app native client
 #[derive(Parser, Debug)]
@@ -26,154 +56,807 @@ struct Args {
     /// Server URL.
     #[arg(short, long, default_value = "ws://localhost:8080/ws")]
     url: String,
+
+    /// Account name.
+    #[arg(long, env = "CLIENT_USER")]
+    user: String,
+
+    /// Account password.
+    #[arg(long, env = "CLIENT_PASSWORD", hide_env_values = true)]
+    password: String,
+
+    /// Register new account before connecting.
+    #[arg(long)]
+    register: bool,
+
+    #[command(flatten)]
+    tls: TlsArgs,
+}
+
+/// Rooms joined and session of user, kept between connections.
+#[derive(Debug)]
+struct Client {
+    /// Joined rooms, current room last.
+    joined_rooms: Vec<String>,
+
+    /// Session to resume after reconnecting.
+    session: Option<Session>,
+
+    /// Time session was established (or resumed) on current connection.
+    connected_since: Option<Instant>,
+
+    /// Number of failed attempts to resume session still in use by previous connection.
+    session_in_use_retries: u32,
+
+    /// Id of last displayed room message.
+    last_seen_message_id: u64,
+
+    /// Status chosen by user.
+    status: PresenceStatus,
+
+    /// Whether user entered anything since last heartbeat.
+    active: bool,
+
+    /// Room user is typing message to and time typing indicator was last refreshed.
+    typing_in: Option<(String, Instant)>,
+
+    /// Other users typing messages (with `typing` set).
+    typing: Vec<Typing>,
+}
+
+impl Client {
+    fn new() -> Self {
+        Client {
+            joined_rooms: vec![DEFAULT_ROOM.to_string()],
+            session: None,
+            connected_since: None,
+            session_in_use_retries: 0,
+            last_seen_message_id: 0,
+            status: PresenceStatus::Online,
+            active: false,
+            typing_in: None,
+            typing: vec![],
+        }
+    }
+
+    fn prompt(&self, connection_state: ConnectionState) -> String {
+        let room = self.joined_rooms.last();
+        let typing: Vec<&str> = self
+            .typing
+            .iter()
+            .filter(|typing| Some(&typing.room) == room)
+            .map(|typing| typing.user.as_str())
+            .collect();
+        prompt(connection_state, room, &typing)
+    }
+
+    /// Update typing indicator of other user.
+    fn update_typing(&mut self, update: Typing) {
+        self.typing
+            .retain(|typing| typing.room != update.room || typing.user != update.user);
+        if update.typing {
+            self.typing.push(update);
+        }
+    }
+}
+
+/// State of connection to server, displayed in prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnectionState {
+    Connecting,
+    Connected,
+}
+
+/// Reason why connection handling ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    /// User wants to quit.
+    Quit,
+
+    /// Connection to server was lost.
+    Disconnected,
+
+    /// Server closed the session (user was kicked or banned).
+    Closed,
+}
+
+/// Await RPC call, returning [Exit::Disconnected] (or `Some` of it) from enclosing function
+/// if connection to server was lost.
+macro_rules! rpc {
+    ($call:expr) => {
+        match $call.await {
+            Ok(result) => result,
+            Err(_) => return Ok(Exit::Disconnected.into()),
+        }
+    };
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    let server = Server {
+        url: Url::parse(&args.url)?,
+        credentials: Credentials {
+            name: args.user,
+            password: args.password,
+        },
+        clients: Clients::new(&args.tls)?,
+    };
 
     println!("Hello.");
 
-    println!("Connecting to server...");
-    let url = Url::parse(&args.url)?;
-    let (web_socket, _) = connect_async(url).await?;
-    let codec = Codec::default();
-    let transport = Transport::new(web_socket, codec);
-    let consumer = Consumer::consume(transport, Configuration::default());
-    println!("Connected.");
+    if args.register {
+        server.authenticate(REGISTER_PATH).await?;
+        println!("Account <{}> registered.", server.credentials.name);
+    }
 
-    let user_name = consumer.user_name().await.unwrap();
-    let connected_user_names = consumer.user_names().await.unwrap();
-    println!("Your name: <{user_name}>.");
+    let mut client = Client::new();
+    let (mut readline, mut stdout) = Readline::new(client.prompt(ConnectionState::Connecting))?;
 
-    if !connected_user_names.is_empty() {
-        println!(
-            "Other connected users: {}.",
-            connected_user_names
-                .iter()
-                .map(|user| format!("<{user}>"))
-                .collect::<Vec<String>>()
-                .join(", ")
-        );
+    writeln!(stdout, "Type '/help' to list commands.")?;
+
+    let mut backoff = Backoff::default();
+    let mut delay = Duration::ZERO;
+    loop {
+        readline.update_prompt(&client.prompt(ConnectionState::Connecting))?;
+        let Some(consumer) =
+            reconnect(&server, delay, &mut backoff, &mut readline, &mut stdout).await?
+        else {
+            break;
+        };
+
+        match run(&consumer, &mut client, &mut readline, &mut stdout).await? {
+            Exit::Quit | Exit::Closed => break,
+            Exit::Disconnected => {
+                // connections lost right after connecting keep backing off
+                if client
+                    .connected_since
+                    .take()
+                    .is_some_and(|since| since.elapsed() >= STABLE_CONNECTION_TIME)
+                {
+                    backoff.reset();
+                }
+                delay = backoff.next_delay();
+                writeln!(
+                    stdout,
+                    "Server disconnected, reconnecting in {:.1} s...",
+                    delay.as_secs_f64()
+                )?;
+            }
+        }
     }
 
-    let (mut readline, mut stdout) = Readline::new("> ".to_string())?;
+    writeln!(stdout, "Exiting...")?;
+    readline.flush()?;
 
-    writeln!(
-        stdout,
-        "Type 'fibonacci(n)' to calculate n-th element of Fibonacci sequence."
-    )?;
-    writeln!(stdout, "Type 'n!' to calculate factorial on n.")?;
+    Ok(())
+}
+
+/// Server address and everything needed to connect to it.
+struct Server {
+    url: Url,
+    credentials: Credentials,
+    clients: Clients,
+}
 
-    {
-        let mut messages = consumer.messages().await.unwrap();
-        let mut connected = consumer.connected().await.unwrap();
-        let mut disconnected = consumer.disconnected().await.unwrap();
+impl Server {
+    /// Send credentials to authentication endpoint at given path and return received token.
+    async fn authenticate(&self, path: &str) -> Result<String> {
+        let mut endpoint = self.url.clone();
+        let scheme = if self.url.scheme() == "wss" {
+            "https"
+        } else {
+            "http"
+        };
+        endpoint
+            .set_scheme(scheme)
+            .map_err(|_| anyhow!("invalid server URL: {}", self.url))?;
+        endpoint.set_path(path);
+        endpoint.set_query(None);
+
+        let response = self
+            .clients
+            .http
+            .post(endpoint)
+            .json(&self.credentials)
+            .send()
+            .await?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(response.json::<Token>().await?.token)
+        } else {
+            match response.json::<AuthError>().await {
+                Ok(error) => Err(error.into()),
+                Err(_) => bail!("authentication failed: server responded with {status}"),
+            }
+        }
+    }
+
+    /// Log in and connect to server with received token.
+    async fn connect(&self) -> Result<Consumer<impl Debug + Send + 'static>> {
+        let token = self.authenticate(LOGIN_PATH).await?;
+        let mut request = self.url.as_str().into_client_request()?;
+        request
+            .headers_mut()
+            .insert(AUTHORIZATION, format!("Bearer {token}").parse()?);
+        let (web_socket, _) =
+            connect_async_tls_with_config(request, None, Some(self.clients.websocket.clone()))
+                .await?;
+        let codec = Codec::default();
+        let transport = Transport::new(web_socket, codec);
+        Ok(Consumer::consume(transport, Configuration::default()))
+    }
+}
+
+/// Connect to server after `delay`, retrying with exponential backoff until connection
+/// succeeds.
+///
+/// Returns `None` if user quits in the meantime, fails if server rejects credentials.
+async fn reconnect(
+    server: &Server,
+    mut delay: Duration,
+    backoff: &mut Backoff,
+    readline: &mut Readline,
+    stdout: &mut Output,
+) -> Result<Option<Consumer<impl Debug + Send + 'static>>> {
+    loop {
+        let connection = async move {
+            sleep(delay).await;
+            server.connect().await
+        }
+        .fuse();
+        pin_mut!(connection);
 
-        loop {
+        let result = loop {
             select! {
-                message = messages.next() => {
-                    if let Some((user_name, message)) = message {
-                        writeln!(stdout, "<{user_name}> {message}")?;
-                    } else {
-                        writeln!(stdout, "Server disconnected.")?;
-                        writeln!(stdout, "Exiting...")?;
-                        break;
+                result = connection => break result,
+                input = readline.next_input().fuse() => match input {
+                    Ok(Input::Line(_)) => {
+                        writeln!(stdout, "Error: not connected to server.")?;
                     }
-                },
-                connected = connected.next() => {
-                    if let Some(user_name) = connected {
-                        writeln!(stdout, "New user connected: <{user_name}>.")?;
+                    Ok(Input::Edited(_)) => {}
+                    Ok(Input::Eof | Input::Interrupted) => return Ok(None),
+                    Err(error) => {
+                        writeln!(stdout, "Error occurred while handling command: {error}")?;
+                        return Ok(None);
                     }
                 },
-                disconnected = disconnected.next() => {
-                    if let Some(user_name) = disconnected {
-                        writeln!(stdout, "User <{user_name}> left.")?;
+            }
+        };
+
+        match result {
+            Ok(consumer) => return Ok(Some(consumer)),
+            Err(error) if error.is::<AuthError>() => return Err(error),
+            Err(error) => {
+                delay = backoff.next_delay();
+                writeln!(
+                    stdout,
+                    "Unable to connect to server: {error}, retrying in {:.1} s...",
+                    delay.as_secs_f64()
+                )?;
+            }
+        }
+    }
+}
+
+/// Resume (or restore) session, subscribe to server events and handle user commands
+/// until user quits or connection is lost.
+async fn run<Error>(
+    consumer: &Consumer<Error>,
+    client: &mut Client,
+    readline: &mut Readline,
+    stdout: &mut Output,
+) -> Result<Exit>
+where
+    Error: Debug + Send + 'static,
+{
+    if let Some(session) = client.session.take() {
+        match rpc!(consumer.resume(session.token.clone(), client.last_seen_message_id)) {
+            Ok(missed_messages) => {
+                client.session_in_use_retries = 0;
+                writeln!(stdout, "Reconnected.")?;
+                for (room, event) in missed_messages {
+                    if let MessageEvent::Message(message) = &event {
+                        client.last_seen_message_id = client.last_seen_message_id.max(message.id);
                     }
-                },
-                command = readline.readline().fuse() => match command {
-                    Ok(event) => {
-                        match event {
-                            ReadlineEvent::Line(line) => {
-                                let line = line.trim();
-                                if let Some(captures) = FIBONACCI_PATTERN.captures(line) {
-                                    if let Some(number) = extract_number(&captures) {
-                                        let stdout_clone = stdout.clone();
-                                        spawn(async move { handle_fibonacci(stdout_clone, number).await.unwrap() });
-                                        readline.add_history_entry(line.to_string());
-                                    } else {
-                                        let input = captures.get(1).unwrap().as_str();
-                                        writeln!(stdout, "Error: {input} is not a non-negative integer.")?;
-                                    }
-                                } else if let Some(captures) = FACTORIAL_PATTERN.captures(line) {
-                                    if let Some(number) = extract_number(&captures) {
-                                        let stdout_clone = stdout.clone();
-                                        spawn(async move { handle_factorial(stdout_clone, number).await.unwrap(); });
-                                        readline.add_history_entry(line.to_string());
-                                    } else {
-                                        let input = captures.get(1).unwrap().as_str();
-                                        writeln!(stdout, "Error: {input} is not a non-negative integer.")?;
-                                    }
-                                } else {
-                                    consumer.message(line.to_string()).await.unwrap();
-                                }
-                            }
-                            ReadlineEvent::Eof | ReadlineEvent::Interrupted => {
-                                writeln!(stdout, "Exiting...")?;
-                                break;
-                            },
+                    writeln!(stdout, "{}", format_event(&room, &event))?;
+                }
+            }
+            Err(ChatError::SessionInUse)
+                if client.session_in_use_retries < MAX_SESSION_IN_USE_RETRIES =>
+            {
+                // server hasn't noticed previous connection was lost yet
+                client.session_in_use_retries += 1;
+                client.session = Some(session);
+                return Ok(Exit::Disconnected);
+            }
+            Err(error) => {
+                client.session_in_use_retries = 0;
+                writeln!(
+                    stdout,
+                    "Reconnected, but session couldn't be resumed: {error}."
+                )?;
+                if let Err(error) = rpc!(consumer.set_user_name(session.user_name.clone())) {
+                    writeln!(stdout, "Error: {error}.")?;
+                }
+                if client.status != PresenceStatus::Online {
+                    let _ = rpc!(consumer.set_status(client.status));
+                }
+                if !client.joined_rooms.iter().any(|room| room == DEFAULT_ROOM) {
+                    let _ = rpc!(consumer.leave_room(DEFAULT_ROOM.to_string()));
+                }
+                let mut joined_rooms = vec![];
+                for room in client.joined_rooms.drain(..) {
+                    match rpc!(consumer.join_room(room.clone())) {
+                        Ok(()) => joined_rooms.push(room),
+                        Err(error) => {
+                            writeln!(stdout, "Error: unable to rejoin #{room}: {error}.")?
                         }
-                    },
-                    Err(error) => {
-                        writeln!(stdout, "Error occurred while handling command: {error}")?;
-                        writeln!(stdout, "Exiting...")?;
-                        break;
-                    },
+                    }
+                }
+                client.joined_rooms = joined_rooms;
+            }
+        }
+    } else {
+        writeln!(stdout, "Connected.")?;
+    }
+
+    let session = rpc!(consumer.session())?;
+    client.connected_since = Some(Instant::now());
+    let connected_user_names = rpc!(consumer.user_names());
+    writeln!(stdout, "Your name: <{}>.", session.user_name)?;
+    client.session = Some(session);
+
+    if !connected_user_names.is_empty() {
+        writeln!(
+            stdout,
+            "Other connected users: {}.",
+            format_user_names(&connected_user_names)
+        )?;
+    }
+
+    // pending stream keeps `select_all` from ending when user is not in any room
+    let mut messages = select_all([stream::pending().boxed()]);
+    for room in &client.joined_rooms {
+        messages.push(rpc!(room_messages(
+            consumer,
+            room.clone(),
+            client.last_seen_message_id
+        )));
+    }
+    let mut events = rpc!(consumer.events());
+    let mut inbox = rpc!(consumer.inbox());
+    let mut presence = rpc!(consumer.presence());
+    let mut typing = rpc!(consumer.typing());
+    client.typing.clear();
+    client.typing_in = None;
+    let mut heartbeat = interval(HEARTBEAT_INTERVAL);
+    readline.update_prompt(&client.prompt(ConnectionState::Connected))?;
+
+    loop {
+        select! {
+            message = messages.next() => {
+                if let Some((room, event)) = message {
+                    if let MessageEvent::Message(message) = &event {
+                        client.last_seen_message_id = client.last_seen_message_id.max(message.id);
+                        if client.typing.iter().any(|typing| typing.user == message.sender_name) {
+                            client.update_typing(Typing {
+                                room: room.clone(),
+                                user: message.sender_name.clone(),
+                                typing: false,
+                            });
+                            readline.update_prompt(&client.prompt(ConnectionState::Connected))?;
+                        }
+                    }
+                    writeln!(stdout, "{}", format_event(&room, &event))?;
+                }
+            },
+            event = events.next() => {
+                if let Some(event) = event {
+                    writeln!(stdout, "{event}")?;
+                    if matches!(event, ServerEvent::Kicked { .. } | ServerEvent::Banned { .. }) {
+                        return Ok(Exit::Closed);
+                    }
+                } else {
+                    return Ok(Exit::Disconnected);
+                }
+            },
+            direct_message = inbox.next() => {
+                if let Some((user_name, message)) = direct_message {
+                    writeln!(stdout, "<{user_name}> -> you: {message}")?;
+                }
+            },
+            update = presence.next() => {
+                if let Some(update) = update {
+                    writeln!(stdout, "{update}")?;
+                }
+            },
+            update = typing.next() => {
+                if let Some(update) = update {
+                    client.update_typing(update);
+                    readline.update_prompt(&client.prompt(ConnectionState::Connected))?;
+                }
+            },
+            _ = heartbeat.tick().fuse() => {
+                rpc!(consumer.heartbeat(client.active));
+                client.active = false;
+            },
+            input = readline.next_input().fuse() => match input {
+                Ok(Input::Edited(line)) => {
+                    let Some(room) = client.joined_rooms.last() else {
+                        continue;
+                    };
+                    // commands aren't sent to room
+                    if line.trim().is_empty() || line.starts_with('/') {
+                        if let Some((room, _)) = client.typing_in.take() {
+                            rpc!(consumer.typing_stopped(room));
+                        }
+                        continue;
+                    }
+                    let refresh = match &client.typing_in {
+                        Some((typing_room, refreshed)) => {
+                            typing_room != room || refreshed.elapsed() >= TYPING_REFRESH_INTERVAL
+                        }
+                        None => true,
+                    };
+                    if refresh {
+                        client.typing_in = Some((room.clone(), Instant::now()));
+                        let _ = rpc!(consumer.typing_started(room.clone()));
+                    }
+                }
+                Ok(Input::Line(line)) => {
+                    client.active = true;
+                    // sending message stops typing too, but commands don't
+                    if let Some((room, _)) = client.typing_in.take() {
+                        rpc!(consumer.typing_stopped(room));
+                    }
+                    let line = line.trim();
+                    if !line.is_empty() {
+                        readline.add_history_entry(line.to_string());
+                        if let Some(exit) = handle_command(line, consumer, client, &mut messages, readline, stdout).await? {
+                            return Ok(exit);
+                        }
+                    }
+                },
+                Ok(Input::Eof | Input::Interrupted) => return Ok(Exit::Quit),
+                Err(error) => {
+                    writeln!(stdout, "Error occurred while handling command: {error}")?;
+                    return Ok(Exit::Quit);
+                },
+            },
+        }
+    }
+}
+
+fn prompt(connection_state: ConnectionState, room: Option<&String>, typing: &[&str]) -> String {
+    let indicator = match connection_state {
+        ConnectionState::Connecting => "[connecting] ",
+        ConnectionState::Connected => "",
+    };
+    let typing = match typing {
+        [] => String::new(),
+        [user] => format!(" [{user} is typing]"),
+        users => format!(" [{} are typing]", users.join(", ")),
+    };
+    match room {
+        Some(room) => format!("{indicator}#{room}{typing}> "),
+        None => format!("{indicator}> "),
+    }
+}
+
+/// Handle line entered by user, return `Some` if [run] should end.
+async fn handle_command<Error>(
+    line: &str,
+    consumer: &Consumer<Error>,
+    client: &mut Client,
+    messages: &mut SelectAll<BoxStream<'static, (String, MessageEvent)>>,
+    readline: &mut Readline,
+    stdout: &mut Output,
+) -> Result<Option<Exit>>
+where
+    Error: Debug + Send + 'static,
+{
+    let command = match Command::parse(line) {
+        // action is sent as a message with special prefix
+        Ok(Command::Me(action)) => Command::Message(Command::action_message(&action)),
+        Ok(command) => command,
+        Err(error) => {
+            writeln!(stdout, "Error: {error}.")?;
+            return Ok(None);
+        }
+    };
+    match command {
+        Command::Message(message) => {
+            if let Some(room) = client.joined_rooms.last() {
+                if let Err(error) = rpc!(consumer.message(room.clone(), message, None)) {
+                    writeln!(stdout, "Error: {error}.")?;
+                }
+            } else {
+                writeln!(stdout, "Error: join a room to send messages.")?;
+            }
+        }
+        Command::Reply { id, message } => {
+            if let Some(room) = client.joined_rooms.last() {
+                if let Err(error) = rpc!(consumer.message(room.clone(), message, Some(id))) {
+                    writeln!(stdout, "Error: {error}.")?;
+                }
+            } else {
+                writeln!(stdout, "Error: join a room to send messages.")?;
+            }
+        }
+        Command::Thread(id) => match rpc!(consumer.thread(id)) {
+            Ok(thread) => {
+                for message in thread {
+                    writeln!(stdout, "{}", format_message(&message))?;
+                }
+            }
+            Err(error) => writeln!(stdout, "Error: {error}.")?,
+        },
+        Command::Me(_) => unreachable!("action is converted to message"),
+        Command::Help(Some(help)) => writeln!(stdout, "{help}")?,
+        Command::Help(None) => {
+            for help in COMMANDS {
+                writeln!(stdout, "{help}")?;
+            }
+        }
+        Command::Nick(name) => match rpc!(consumer.set_user_name(name.clone())) {
+            Ok(()) => {
+                if let Some(session) = &mut client.session {
+                    session.user_name = name.clone();
+                }
+                writeln!(stdout, "Your name: <{name}>.")?;
+            }
+            Err(error) => writeln!(stdout, "Error: {error}.")?,
+        },
+        Command::Rooms => {
+            let rooms = rpc!(consumer.rooms());
+            writeln!(
+                stdout,
+                "Rooms: {}.",
+                rooms
+                    .iter()
+                    .map(|room| format!("#{room}"))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )?;
+        }
+        Command::Create(room) => match rpc!(consumer.create_room(room.clone())) {
+            Ok(()) => {
+                messages.push(rpc!(room_messages(consumer, room.clone(), 0)));
+                writeln!(stdout, "Created room #{room}.")?;
+                client.joined_rooms.push(room);
+                readline.update_prompt(&client.prompt(ConnectionState::Connected))?;
+            }
+            Err(error) => writeln!(stdout, "Error: {error}.")?,
+        },
+        Command::Join(room) => match rpc!(consumer.join_room(room.clone())) {
+            Ok(()) => {
+                if !client.joined_rooms.contains(&room) {
+                    messages.push(rpc!(room_messages(consumer, room.clone(), 0)));
+                }
+                client
+                    .joined_rooms
+                    .retain(|joined_room| joined_room != &room);
+                let members = rpc!(consumer.room_members(room.clone())).unwrap_or_default();
+                writeln!(
+                    stdout,
+                    "Joined room #{room}, members: {}.",
+                    format_user_names(&members)
+                )?;
+                let topic = rpc!(consumer.topic(room.clone())).unwrap_or_default();
+                if !topic.is_empty() {
+                    writeln!(stdout, "Topic of #{room}: {topic}")?;
+                }
+                client.joined_rooms.push(room);
+                readline.update_prompt(&client.prompt(ConnectionState::Connected))?;
+            }
+            Err(error) => writeln!(stdout, "Error: {error}.")?,
+        },
+        Command::Leave => {
+            if let Some(room) = client.joined_rooms.pop() {
+                match rpc!(consumer.leave_room(room.clone())) {
+                    Ok(()) => writeln!(stdout, "Left room #{room}.")?,
+                    Err(error) => writeln!(stdout, "Error: {error}.")?,
+                }
+                readline.update_prompt(&client.prompt(ConnectionState::Connected))?;
+            } else {
+                writeln!(stdout, "Error: you are not in any room.")?;
+            }
+        }
+        Command::Topic(topic) => {
+            let Some(room) = client.joined_rooms.last().cloned() else {
+                writeln!(stdout, "Error: you are not in any room.")?;
+                return Ok(None);
+            };
+            match topic {
+                Some(topic) => {
+                    if let Err(error) = rpc!(consumer.set_topic(room, topic)) {
+                        writeln!(stdout, "Error: {error}.")?;
+                    }
+                }
+                None => match rpc!(consumer.topic(room.clone())) {
+                    Ok(topic) if topic.is_empty() => {
+                        writeln!(stdout, "Room #{room} has no topic.")?
+                    }
+                    Ok(topic) => writeln!(stdout, "Topic of #{room}: {topic}")?,
+                    Err(error) => writeln!(stdout, "Error: {error}.")?,
                 },
             }
         }
+        Command::Who(room) => {
+            if let Some(room) = room.or_else(|| client.joined_rooms.last().cloned()) {
+                match rpc!(consumer.room_members(room.clone())) {
+                    Ok(members) => writeln!(
+                        stdout,
+                        "Members of #{room}: {}.",
+                        format_user_names(&members)
+                    )?,
+                    Err(error) => writeln!(stdout, "Error: {error}.")?,
+                }
+            } else {
+                writeln!(stdout, "Error: you are not in any room.")?;
+            }
+        }
+        Command::Status(Some(status)) => match rpc!(consumer.set_status(status)) {
+            Ok(()) => client.status = status,
+            Err(error) => writeln!(stdout, "Error: {error}.")?,
+        },
+        Command::Status(None) => {
+            let mut presences = rpc!(consumer.presences());
+            presences.sort_by(|a, b| a.user.cmp(&b.user));
+            for presence in presences {
+                writeln!(stdout, "<{}> - {}", presence.user, presence.status)?;
+            }
+        }
+        Command::Seen(user) => match rpc!(consumer.user_presence(user)) {
+            Ok(presence) => writeln!(stdout, "{}", format_presence(&presence))?,
+            Err(error) => writeln!(stdout, "Error: {error}.")?,
+        },
+        Command::Msg { user, message } => {
+            match rpc!(consumer.direct_message(user.clone(), message.clone())) {
+                Ok(()) => writeln!(stdout, "you -> <{user}>: {message}")?,
+                Err(error) => writeln!(stdout, "Error: {error}.")?,
+            }
+        }
+        Command::Edit { id, message } => {
+            if let Err(error) = rpc!(consumer.edit_message(id, message)) {
+                writeln!(stdout, "Error: {error}.")?;
+            }
+        }
+        Command::Delete(id) => {
+            if let Err(error) = rpc!(consumer.delete_message(id)) {
+                writeln!(stdout, "Error: {error}.")?;
+            }
+        }
+        Command::Revisions(id) => match rpc!(consumer.revisions(id)) {
+            Ok(revisions) if revisions.is_empty() => {
+                writeln!(stdout, "Message {id} wasn't changed.")?
+            }
+            Ok(revisions) => {
+                for revision in revisions {
+                    writeln!(stdout, "{}", format_revision(&revision))?;
+                }
+            }
+            Err(error) => writeln!(stdout, "Error: {error}.")?,
+        },
+        Command::React { id, emoji } => {
+            if let Err(error) = rpc!(consumer.react(id, emoji)) {
+                writeln!(stdout, "Error: {error}.")?;
+            }
+        }
+        Command::Unreact { id, emoji } => {
+            if let Err(error) = rpc!(consumer.unreact(id, emoji)) {
+                writeln!(stdout, "Error: {error}.")?;
+            }
+        }
+        Command::Fibonacci(number) => {
+            let stdout = stdout.clone();
+            spawn(async move { handle_fibonacci(stdout, number).await.unwrap() });
+        }
+        Command::Factorial(number) => {
+            let stdout = stdout.clone();
+            spawn(async move { handle_factorial(stdout, number).await.unwrap() });
+        }
+        Command::Kick { user, reason } => match rpc!(consumer.kick(user.clone(), reason)) {
+            Ok(()) => writeln!(stdout, "Kicked <{user}>.")?,
+            Err(error) => writeln!(stdout, "Error: {error}.")?,
+        },
+        Command::Ban {
+            user,
+            scope,
+            duration,
+            reason,
+        } => match rpc!(consumer.ban(user.clone(), scope, duration, reason)) {
+            Ok(()) => writeln!(stdout, "Banned <{user}>.")?,
+            Err(error) => writeln!(stdout, "Error: {error}.")?,
+        },
+        Command::Unban(target) => match rpc!(consumer.unban(target.clone())) {
+            Ok(()) => writeln!(stdout, "Lifted ban of {target}.")?,
+            Err(error) => writeln!(stdout, "Error: {error}.")?,
+        },
+        Command::Mute { user, duration } => match rpc!(consumer.mute(user.clone(), duration)) {
+            Ok(()) => writeln!(stdout, "Muted <{user}>.")?,
+            Err(error) => writeln!(stdout, "Error: {error}.")?,
+        },
+        Command::Unmute(user) => match rpc!(consumer.unmute(user.clone())) {
+            Ok(()) => writeln!(stdout, "Unmuted <{user}>.")?,
+            Err(error) => writeln!(stdout, "Error: {error}.")?,
+        },
+        Command::Notice(message) => {
+            if let Err(error) = rpc!(consumer.notice(message)) {
+                writeln!(stdout, "Error: {error}.")?;
+            }
+        }
     }
+    Ok(None)
+}
 
-    readline.flush()?;
+/// Stream of events of room,
+/// starting with last [HISTORY_LENGTH] messages sent to the room after message with id `after`.
+async fn room_messages<Error>(
+    consumer: &Consumer<Error>,
+    room: String,
+    after: u64,
+) -> Result<BoxStream<'static, (String, MessageEvent)>, zzrpc::Error<Error>>
+where
+    Error: Debug + Send + 'static,
+{
+    let messages = consumer.messages(room.clone(), true).await?;
+    let mut history = consumer
+        .history(room.clone(), None, HISTORY_LENGTH)
+        .await?
+        .unwrap_or_default();
+    history.retain(|message| message.id > after);
+    // skip messages that were sent between subscribing and requesting history
+    let last_id = history.last().map_or(after, |message| message.id);
+    let messages = messages.filter(move |event| {
+        future::ready(match event {
+            MessageEvent::Message(message) => message.id > last_id,
+            MessageEvent::Edited(_)
+            | MessageEvent::Deleted { .. }
+            | MessageEvent::Reactions { .. }
+            | MessageEvent::Gap { .. }
+            | MessageEvent::UpdatesGap { .. } => true,
+        })
+    });
+    Ok(stream::iter(history.into_iter().map(MessageEvent::Message))
+        .chain(messages)
+        .map(move |event| (room.clone(), event))
+        .boxed())
+}
 
-    Ok(())
+fn format_event(room: &str, event: &MessageEvent) -> String {
+    match event {
+        MessageEvent::Message(message) | MessageEvent::Edited(message) => format_message(message),
+        MessageEvent::Deleted { id } => format!("[#{room}] (message {id} deleted)"),
+        MessageEvent::Reactions { id, reactions } => format_reactions_update(room, *id, reactions),
+        MessageEvent::Gap { missed } => format!("[#{room}] ({missed} messages missed)"),
+        MessageEvent::UpdatesGap { missed } => {
+            format!("[#{room}] ({missed} message updates missed, some messages may be out of date)")
+        }
+    }
 }
 
-fn extract_number<'a>(captures: &'a Captures<'a>) -> Option<u64> {
-    captures
-        .get(1)
-        .and_then(|capture| capture.as_str().parse::<u64>().ok())
+/// Format message like [chat::format_message], replies are indented.
+fn format_message(message: &ChatMessage) -> String {
+    let formatted = chat::format_message(message);
+    if message.reply_to.is_some() {
+        format!("    {formatted}")
+    } else {
+        formatted
+    }
 }
 
-async fn handle_fibonacci(mut stdout: SharedWriter, number: u64) -> Result<()> {
+async fn handle_fibonacci(mut stdout: Output, number: u64) -> Result<()> {
     writeln!(stdout, "Calculating fibonacci({number})...")?;
     let result = tokio_rayon::spawn(move || common::fibonacci(number)).await;
     writeln!(stdout, "fibonacci({number}) = {result}")?;
     Ok(())
 }
 
-async fn handle_factorial(mut stdout: SharedWriter, number: u64) -> Result<()> {
+async fn handle_factorial(mut stdout: Output, number: u64) -> Result<()> {
     writeln!(stdout, "Calculating {number}!...")?;
     let result = tokio_rayon::spawn(move || common::factorial(number)).await;
     writeln!(stdout, "{number}! = {result}")?;
     Ok(())
 }
-
-#[cfg(test)]
-mod tests {
-    use crate::{extract_number, FACTORIAL_PATTERN, FIBONACCI_PATTERN};
-
-    #[test]
-    fn test_fibonacci_pattern() {
-        let captures = FIBONACCI_PATTERN.captures("fibonacci(123)").unwrap();
-        let number = extract_number(&captures).unwrap();
-        assert_eq!(number, 123);
-    }
-
-    #[test]
-    fn test_factorial_pattern() {
-        let captures = FACTORIAL_PATTERN.captures("0!").unwrap();
-        let number = extract_number(&captures).unwrap();
-        assert_eq!(number, 0);
-    }
-}
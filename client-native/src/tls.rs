@@ -0,0 +1,140 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use anyhow::{bail, Context, Result};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName,
+};
+use rustls_pemfile::Item;
+use tokio_tungstenite::Connector;
+
+/// TLS options.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct TlsArgs {
+    /// File with PEM encoded CA certificates to trust in addition to built-in ones.
+    #[arg(long)]
+    pub ca_cert: Option<PathBuf>,
+
+    /// File with PEM encoded client certificate chain for mutual TLS.
+    #[arg(long, requires = "client_key")]
+    pub client_cert: Option<PathBuf>,
+
+    /// File with PEM encoded private key of client certificate.
+    #[arg(long, requires = "client_cert")]
+    pub client_key: Option<PathBuf>,
+
+    /// Don't verify server certificate (for local testing against self-signed servers only).
+    #[arg(long)]
+    pub insecure: bool,
+}
+
+/// Clients used to connect to server, configured according to [TlsArgs].
+#[derive(Clone)]
+pub struct Clients {
+    /// Client for authentication requests.
+    pub http: reqwest::Client,
+
+    /// Connector for web socket connections.
+    pub websocket: Connector,
+}
+
+impl Clients {
+    pub fn new(args: &TlsArgs) -> Result<Self> {
+        let ca_certificates = match &args.ca_cert {
+            Some(path) => load_certificates(path)?,
+            None => vec![],
+        };
+        let identity = match (&args.client_cert, &args.client_key) {
+            (Some(cert), Some(key)) => Some((load_certificates(cert)?, load_private_key(key)?)),
+            _ => None,
+        };
+
+        let mut http = reqwest::Client::builder()
+            .use_rustls_tls()
+            .danger_accept_invalid_certs(args.insecure);
+        for certificate in &ca_certificates {
+            http = http.add_root_certificate(reqwest::Certificate::from_der(&certificate.0)?);
+        }
+        if let (Some(cert), Some(key)) = (&args.client_cert, &args.client_key) {
+            let mut pem = std::fs::read(cert)?;
+            pem.extend(std::fs::read(key)?);
+            http = http.identity(reqwest::Identity::from_pem(&pem)?);
+        }
+
+        let mut roots = RootCertStore::empty();
+        roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+        for certificate in &ca_certificates {
+            roots.add(certificate).context("invalid CA certificate")?;
+        }
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let mut websocket = match identity {
+            Some((chain, key)) => builder
+                .with_single_cert(chain, key)
+                .context("invalid client certificate")?,
+            None => builder.with_no_client_auth(),
+        };
+        if args.insecure {
+            websocket
+                .dangerous()
+                .set_certificate_verifier(Arc::new(NoVerification));
+        }
+
+        Ok(Clients {
+            http: http.build()?,
+            websocket: Connector::Rustls(Arc::new(websocket)),
+        })
+    }
+}
+
+fn load_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let file = File::open(path).with_context(|| format!("unable to open {path:?}"))?;
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(file))
+        .with_context(|| format!("unable to read certificates from {path:?}"))?;
+    if certificates.is_empty() {
+        bail!("no certificates found in {path:?}");
+    }
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKey> {
+    let file = File::open(path).with_context(|| format!("unable to open {path:?}"))?;
+    for item in rustls_pemfile::read_all(&mut BufReader::new(file))
+        .with_context(|| format!("unable to read private key from {path:?}"))?
+    {
+        if let Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) = item {
+            return Ok(PrivateKey(key));
+        }
+    }
+    bail!("no private key found in {path:?}")
+}
+
+/// Certificate verifier accepting any server certificate.
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
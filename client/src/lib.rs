@@ -1,17 +1,40 @@
-use std::rc::Rc;
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Display,
+    rc::Rc,
+};
 
-use common::api::{self, chat::Api as ChatApi, worker::Api as WorkerApi};
-use futures::StreamExt;
+use chrono::{DateTime, Utc};
+use common::api::{
+    self,
+    auth::{AuthError, Credentials, Token, LOGIN_PATH, REGISTER_PATH},
+    chat::{
+        format_message, format_presence, format_reactions_update, format_revision,
+        format_user_names, Api as ChatApi, MessageEvent, Typing, DEFAULT_ROOM, HEARTBEAT_INTERVAL,
+        TYPING_REFRESH_INTERVAL,
+    },
+    worker::Api as WorkerApi,
+};
+use common::command::{Command, COMMANDS};
+use futures::{channel::mpsc, StreamExt};
 use kodec::binary::Codec;
 use wasm_bindgen::{prelude::*, JsCast};
+use wasm_bindgen_futures::JsFuture;
 
-use js_utils::{console_log, document, event::When, set_panic_hook, spawn, window};
+use js_utils::{console_log, document, event::When, set_panic_hook, sleep, spawn, window};
 use web_sys::{
-    HtmlInputElement, HtmlTextAreaElement, KeyboardEvent, MouseEvent, WebSocket, Worker,
+    HtmlInputElement, HtmlTextAreaElement, KeyboardEvent, MouseEvent, Request, RequestInit,
+    Response, WebSocket, Worker,
 };
 
 use zzrpc::consumer::{Configuration, Consume};
 
+/// Number of past messages displayed after joining a room.
+const HISTORY_LENGTH: usize = 20;
+
+/// Room user is typing message to and time typing indicator was last refreshed.
+type TypingIn = Rc<RefCell<Option<(String, DateTime<Utc>)>>>;
+
 #[wasm_bindgen(start)]
 pub async fn main_client() -> Result<(), JsValue> {
     set_panic_hook();
@@ -38,13 +61,20 @@ pub async fn main_client() -> Result<(), JsValue> {
 
     write_line("Hello.");
 
+    write_line("Log in or register to connect.");
+    let token = log_in(write_line.clone()).await?;
+
     // setting up web socket
     write_line("Connecting to server...");
-    let host = window()
-        .location()
+    let location = window().location();
+    let host = location
         .host()
         .expect("couldn't extract host from location");
-    let url = format!("ws://{host}/ws");
+    let scheme = match location.protocol() {
+        Ok(protocol) if protocol == "https:" => "wss",
+        _ => "ws",
+    };
+    let url = format!("{scheme}://{host}/ws?token={token}");
     let web_socket = Rc::new(WebSocket::new(&url).unwrap());
     let transport = mezzenger_websocket::Transport::new(&web_socket, Codec::default())
         .await
@@ -70,34 +100,53 @@ pub async fn main_client() -> Result<(), JsValue> {
             .unwrap(),
     );
 
-    let number = Rc::new(
-        document
-            .get_element_by_id("number")
-            .unwrap()
-            .dyn_into::<HtmlInputElement>()
-            .unwrap(),
-    );
+    let joined_rooms = Rc::new(RefCell::new(vec![DEFAULT_ROOM.to_string()]));
+    // whether user entered anything since last heartbeat
+    let active = Rc::new(Cell::new(false));
+    let typing_in: TypingIn = Rc::new(RefCell::new(None));
 
     let input_clone = input.clone();
     let chat_consumer_clone = chat_consumer.clone();
+    let worker_consumer_clone = worker_consumer.clone();
+    let joined_rooms_clone = joined_rooms.clone();
     let write_line_clone = write_line.clone();
+    let active_clone = active.clone();
+    let typing_in_clone = typing_in.clone();
     let send = move || {
+        active_clone.set(true);
         let text = input_clone.value().trim().to_string();
-        let chat_consumer = chat_consumer_clone.clone();
-        let write_line_clone = write_line_clone.clone();
-        spawn(async move {
-            let _ = chat_consumer.message(text).await.map_err(|error| {
-                write_line_clone(&format!("Error occurred while sending message: {error}."));
+        // sending message stops typing too, but commands don't
+        if let Some((room, _)) = typing_in_clone.take() {
+            let chat_consumer = chat_consumer_clone.clone();
+            spawn(async move {
+                let _ = chat_consumer.typing_stopped(room).await;
             });
-        });
+        }
+        spawn(handle_input(
+            text,
+            chat_consumer_clone.clone(),
+            worker_consumer_clone.clone(),
+            joined_rooms_clone.clone(),
+            write_line_clone.clone(),
+        ));
         input_clone.set_value("");
     };
 
     let send_clone = send.clone();
+    let input_clone = input.clone();
+    let chat_consumer_clone = chat_consumer.clone();
+    let joined_rooms_clone = joined_rooms.clone();
     let _input_handler = input
         .when("keyup", move |event: KeyboardEvent| {
             if event.key() == "Enter" {
                 send_clone();
+            } else {
+                update_typing(
+                    !input_clone.value().trim().is_empty(),
+                    &chat_consumer_clone,
+                    joined_rooms_clone.borrow().last(),
+                    &typing_in,
+                );
             }
         })
         .unwrap();
@@ -108,99 +157,82 @@ pub async fn main_client() -> Result<(), JsValue> {
         })
         .unwrap();
 
-    let number_clone = number.clone();
-    let write_line_clone = write_line.clone();
-    let get_input = move || {
-        let value = number_clone.value();
-        let input = value.parse::<u64>();
-        if input.is_err() {
-            write_line_clone(&format!("Error: {value} is not a non-negative integer."));
-        }
-        input.ok()
-    };
-
-    let get_input_clone = get_input.clone();
-    let write_line_clone = write_line.clone();
-    let worker_consumer_clone = worker_consumer.clone();
-    let _fibonacci_handler = Rc::new(document.get_element_by_id("fibonacci").unwrap())
-        .when("click", move |_event: MouseEvent| {
-            if let Some(input) = get_input_clone() {
-                write_line_clone(&format!("Calculating fibonacci({input})..."));
-                let worker_consumer_clone = worker_consumer_clone.clone();
-                let write_line_clone = write_line_clone.clone();
-                spawn(async move {
-                    match worker_consumer_clone.fibonacci(input).await {
-                        Ok(result) => {
-                            write_line_clone(&format!("fibonacci({input}) = {result}"));
-                        }
-                        Err(error) => {
-                            write_line_clone(&format!(
-                                "Error occurred while sending message to worker: {error}."
-                            ));
-                        }
-                    }
-                });
-            }
-        })
-        .unwrap();
-
-    let write_line_clone = write_line.clone();
-    let _factorial_handler = Rc::new(document.get_element_by_id("factorial").unwrap())
-        .when("click", move |_event: MouseEvent| {
-            if let Some(input) = get_input() {
-                let worker_consumer_clone = worker_consumer.clone();
-                let write_line_clone = write_line_clone.clone();
-                write_line_clone(&format!("Calculating {input}!..."));
-                spawn(async move {
-                    match worker_consumer_clone.factorial(input).await {
-                        Ok(result) => {
-                            write_line_clone(&format!("{input}! = {result}"));
-                        }
-                        Err(error) => {
-                            write_line_clone(&format!(
-                                "Error occurred while sending message to worker: {error}."
-                            ));
-                        }
-                    }
-                });
-            }
-        })
-        .unwrap();
-
     // handle server messages
-    let user_name = chat_consumer.user_name().await.unwrap();
+    let user_name = chat_consumer
+        .user_name()
+        .await
+        .unwrap()
+        .map_err(|error| JsValue::from_str(&error.to_string()))?;
     let connected_user_names = chat_consumer.user_names().await.unwrap();
     write_line(&format!("Your name: <{user_name}>."));
+    write_line("Type '/help' to list commands.");
     if !connected_user_names.is_empty() {
         write_line(&format!(
             "Other connected users: {}.",
-            connected_user_names
-                .iter()
-                .map(|user| format!("<{user}>"))
-                .collect::<Vec<String>>()
-                .join(", ")
+            format_user_names(&connected_user_names)
         ));
     }
 
+    watch_room(&chat_consumer, DEFAULT_ROOM.to_string(), write_line.clone()).await;
+
     let write_line_clone = write_line.clone();
-    let mut connected = chat_consumer.connected().await.unwrap();
+    let mut inbox = chat_consumer.inbox().await.unwrap();
     spawn(async move {
-        while let Some(user_name) = connected.next().await {
-            write_line_clone(&format!("New user connected: <{user_name}>."));
+        while let Some((user_name, message)) = inbox.next().await {
+            write_line_clone(&format!("<{user_name}> -> you: {message}"));
         }
     });
 
     let write_line_clone = write_line.clone();
-    let mut disconnected = chat_consumer.disconnected().await.unwrap();
+    let mut presence = chat_consumer.presence().await.unwrap();
     spawn(async move {
-        while let Some(user_name) = disconnected.next().await {
-            write_line_clone(&format!("User <{user_name}> left."));
+        while let Some(update) = presence.next().await {
+            write_line_clone(&update.to_string());
         }
     });
 
-    let mut messages = chat_consumer.messages().await.unwrap();
-    while let Some((user_name, message)) = messages.next().await {
-        write_line(&format!("<{user_name}> {message}"));
+    let typing_element = document.get_element_by_id("typing").unwrap();
+    let joined_rooms_clone = joined_rooms.clone();
+    let mut typing = chat_consumer.typing().await.unwrap();
+    spawn(async move {
+        let mut typing_users: Vec<Typing> = vec![];
+        while let Some(update) = typing.next().await {
+            typing_users.retain(|typing| typing.room != update.room || typing.user != update.user);
+            if update.typing {
+                typing_users.push(update);
+            }
+            let room = joined_rooms_clone.borrow().last().cloned();
+            let users: Vec<&str> = typing_users
+                .iter()
+                .filter(|typing| Some(&typing.room) == room.as_ref())
+                .map(|typing| typing.user.as_str())
+                .collect();
+            let text = match users.as_slice() {
+                [] => String::new(),
+                [user] => format!("{user} is typing..."),
+                users => format!("{} are typing...", users.join(", ")),
+            };
+            typing_element.set_text_content(Some(&text));
+        }
+    });
+
+    let chat_consumer_clone = chat_consumer.clone();
+    spawn(async move {
+        loop {
+            sleep(HEARTBEAT_INTERVAL).await;
+            if chat_consumer_clone
+                .heartbeat(active.replace(false))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let mut events = chat_consumer.events().await.unwrap();
+    while let Some(event) = events.next().await {
+        write_line(&event.to_string());
     }
 
     write_line("Server disconnected.");
@@ -208,6 +240,517 @@ pub async fn main_client() -> Result<(), JsValue> {
     Ok(())
 }
 
+/// Wait until user logs in (or registers) using login form and return received token.
+async fn log_in(write_line: impl Fn(&str) + Clone + 'static) -> Result<String, JsValue> {
+    let document = document();
+    let account = document
+        .get_element_by_id("account")
+        .unwrap()
+        .dyn_into::<HtmlInputElement>()
+        .unwrap();
+    let password = document
+        .get_element_by_id("password")
+        .unwrap()
+        .dyn_into::<HtmlInputElement>()
+        .unwrap();
+
+    let (sender, mut receiver) = mpsc::unbounded();
+    let sender_clone = sender.clone();
+    let _log_in_handler = Rc::new(document.get_element_by_id("log_in").unwrap())
+        .when("click", move |_event: MouseEvent| {
+            let _ = sender_clone.unbounded_send(LOGIN_PATH);
+        })
+        .unwrap();
+    let _register_handler = Rc::new(document.get_element_by_id("register").unwrap())
+        .when("click", move |_event: MouseEvent| {
+            let _ = sender.unbounded_send(REGISTER_PATH);
+        })
+        .unwrap();
+
+    while let Some(path) = receiver.next().await {
+        let credentials = Credentials {
+            name: account.value().trim().to_string(),
+            password: password.value(),
+        };
+        match authenticate(&credentials, path).await? {
+            Ok(token) => {
+                password.set_value("");
+                document
+                    .get_element_by_id("login")
+                    .unwrap()
+                    .set_attribute("hidden", "")?;
+                if path == REGISTER_PATH {
+                    write_line(&format!("Account <{}> registered.", credentials.name));
+                }
+                return Ok(token);
+            }
+            Err(error) => write_line(&format!("Error: {error}.")),
+        }
+    }
+    Err(JsValue::from_str("login form was removed"))
+}
+
+/// Send credentials to authentication endpoint at given path and return received token.
+async fn authenticate(
+    credentials: &Credentials,
+    path: &str,
+) -> Result<Result<String, AuthError>, JsValue> {
+    let init = RequestInit::new();
+    init.set_method("POST");
+    let body = serde_json::to_string(credentials).unwrap();
+    init.set_body(&JsValue::from_str(&body));
+    let request = Request::new_with_str_and_init(path, &init)?;
+    request.headers().set("Content-Type", "application/json")?;
+
+    let response: Response = JsFuture::from(window().fetch_with_request(&request))
+        .await?
+        .dyn_into()?;
+    let text = JsFuture::from(response.text()?)
+        .await?
+        .as_string()
+        .unwrap_or_default();
+    if response.ok() {
+        let token: Token =
+            serde_json::from_str(&text).map_err(|error| JsValue::from_str(&error.to_string()))?;
+        Ok(Ok(token.token))
+    } else {
+        serde_json::from_str(&text).map(Err).map_err(|_| {
+            JsValue::from_str(&format!(
+                "authentication failed: server responded with {}",
+                response.status()
+            ))
+        })
+    }
+}
+
+/// Start (or refresh) typing indicator in current room if user is typing,
+/// stop it if input was cleared.
+fn update_typing<Error>(
+    typing: bool,
+    chat_consumer: &Rc<api::chat::Consumer<Error>>,
+    room: Option<&String>,
+    typing_in: &TypingIn,
+) where
+    Error: 'static,
+{
+    let chat_consumer = chat_consumer.clone();
+    match (typing, room) {
+        (true, Some(room)) => {
+            let refresh = match &*typing_in.borrow() {
+                Some((typing_room, refreshed)) => {
+                    typing_room != room
+                        || (Utc::now() - *refreshed).to_std().unwrap_or_default()
+                            >= TYPING_REFRESH_INTERVAL
+                }
+                None => true,
+            };
+            if refresh {
+                typing_in.replace(Some((room.clone(), Utc::now())));
+                let room = room.clone();
+                spawn(async move {
+                    let _ = chat_consumer.typing_started(room).await;
+                });
+            }
+        }
+        (false, _) => {
+            if let Some((room, _)) = typing_in.take() {
+                spawn(async move {
+                    let _ = chat_consumer.typing_stopped(room).await;
+                });
+            }
+        }
+        (true, None) => {}
+    }
+}
+
+async fn handle_input<Error, WorkerError>(
+    text: String,
+    chat_consumer: Rc<api::chat::Consumer<Error>>,
+    worker_consumer: Rc<api::worker::Consumer<WorkerError>>,
+    joined_rooms: Rc<RefCell<Vec<String>>>,
+    write_line: impl Fn(&str) + Clone + 'static,
+) where
+    Error: Display + 'static,
+    WorkerError: Display + 'static,
+{
+    if text.is_empty() {
+        return;
+    }
+    let update_joined_rooms = |room: String| {
+        let mut joined_rooms = joined_rooms.borrow_mut();
+        joined_rooms.retain(|joined_room| joined_room != &room);
+        joined_rooms.push(room);
+    };
+    let command = match Command::parse(&text) {
+        // action is sent as a message with special prefix
+        Ok(Command::Me(action)) => Command::Message(Command::action_message(&action)),
+        Ok(command) => command,
+        Err(error) => {
+            write_line(&format!("Error: {error}."));
+            return;
+        }
+    };
+    let result = match command {
+        Command::Message(message) => {
+            let room = joined_rooms.borrow().last().cloned();
+            if let Some(room) = room {
+                chat_consumer
+                    .message(room, message, None)
+                    .await
+                    .map(|result| {
+                        if let Err(error) = result {
+                            write_line(&format!("Error: {error}."));
+                        }
+                    })
+            } else {
+                write_line("Error: join a room to send messages.");
+                Ok(())
+            }
+        }
+        Command::Reply { id, message } => {
+            let room = joined_rooms.borrow().last().cloned();
+            if let Some(room) = room {
+                chat_consumer
+                    .message(room, message, Some(id))
+                    .await
+                    .map(|result| {
+                        if let Err(error) = result {
+                            write_line(&format!("Error: {error}."));
+                        }
+                    })
+            } else {
+                write_line("Error: join a room to send messages.");
+                Ok(())
+            }
+        }
+        Command::Thread(id) => chat_consumer.thread(id).await.map(|result| match result {
+            Ok(thread) => {
+                for message in thread {
+                    write_line(&format_message(&message));
+                }
+            }
+            Err(error) => write_line(&format!("Error: {error}.")),
+        }),
+        Command::Me(_) => unreachable!("action is converted to message"),
+        Command::Help(Some(help)) => {
+            write_line(&help.to_string());
+            Ok(())
+        }
+        Command::Help(None) => {
+            for help in COMMANDS {
+                write_line(&help.to_string());
+            }
+            Ok(())
+        }
+        Command::Nick(name) => chat_consumer
+            .set_user_name(name.clone())
+            .await
+            .map(|result| match result {
+                Ok(()) => write_line(&format!("Your name: <{name}>.")),
+                Err(error) => write_line(&format!("Error: {error}.")),
+            }),
+        Command::Rooms => chat_consumer.rooms().await.map(|rooms| {
+            write_line(&format!(
+                "Rooms: {}.",
+                rooms
+                    .iter()
+                    .map(|room| format!("#{room}"))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ))
+        }),
+        Command::Create(room) => match chat_consumer.create_room(room.clone()).await {
+            Ok(Ok(())) => {
+                watch_room(&chat_consumer, room.clone(), write_line.clone()).await;
+                write_line(&format!("Created room #{room}."));
+                update_joined_rooms(room);
+                Ok(())
+            }
+            Ok(Err(error)) => {
+                write_line(&format!("Error: {error}."));
+                Ok(())
+            }
+            Err(error) => Err(error),
+        },
+        Command::Join(room) => match chat_consumer.join_room(room.clone()).await {
+            Ok(Ok(())) => {
+                if !joined_rooms.borrow().contains(&room) {
+                    watch_room(&chat_consumer, room.clone(), write_line.clone()).await;
+                }
+                let members = chat_consumer
+                    .room_members(room.clone())
+                    .await
+                    .map(Result::unwrap_or_default)
+                    .unwrap_or_default();
+                write_line(&format!(
+                    "Joined room #{room}, members: {}.",
+                    format_user_names(&members)
+                ));
+                let topic = chat_consumer
+                    .topic(room.clone())
+                    .await
+                    .map(Result::unwrap_or_default)
+                    .unwrap_or_default();
+                if !topic.is_empty() {
+                    write_line(&format!("Topic of #{room}: {topic}"));
+                }
+                update_joined_rooms(room);
+                Ok(())
+            }
+            Ok(Err(error)) => {
+                write_line(&format!("Error: {error}."));
+                Ok(())
+            }
+            Err(error) => Err(error),
+        },
+        Command::Leave => {
+            let room = joined_rooms.borrow_mut().pop();
+            if let Some(room) = room {
+                chat_consumer
+                    .leave_room(room.clone())
+                    .await
+                    .map(|result| match result {
+                        Ok(()) => write_line(&format!("Left room #{room}.")),
+                        Err(error) => write_line(&format!("Error: {error}.")),
+                    })
+            } else {
+                write_line("Error: you are not in any room.");
+                Ok(())
+            }
+        }
+        Command::Topic(topic) => {
+            let room = joined_rooms.borrow().last().cloned();
+            match (room, topic) {
+                (Some(room), Some(topic)) => {
+                    chat_consumer.set_topic(room, topic).await.map(|result| {
+                        if let Err(error) = result {
+                            write_line(&format!("Error: {error}."));
+                        }
+                    })
+                }
+                (Some(room), None) => {
+                    chat_consumer
+                        .topic(room.clone())
+                        .await
+                        .map(|result| match result {
+                            Ok(topic) if topic.is_empty() => {
+                                write_line(&format!("Room #{room} has no topic."))
+                            }
+                            Ok(topic) => write_line(&format!("Topic of #{room}: {topic}")),
+                            Err(error) => write_line(&format!("Error: {error}.")),
+                        })
+                }
+                (None, _) => {
+                    write_line("Error: you are not in any room.");
+                    Ok(())
+                }
+            }
+        }
+        Command::Who(room) => {
+            let room = room.or_else(|| joined_rooms.borrow().last().cloned());
+            if let Some(room) = room {
+                chat_consumer
+                    .room_members(room.clone())
+                    .await
+                    .map(|result| match result {
+                        Ok(members) => write_line(&format!(
+                            "Members of #{room}: {}.",
+                            format_user_names(&members)
+                        )),
+                        Err(error) => write_line(&format!("Error: {error}.")),
+                    })
+            } else {
+                write_line("Error: you are not in any room.");
+                Ok(())
+            }
+        }
+        Command::Status(Some(status)) => chat_consumer.set_status(status).await.map(|result| {
+            if let Err(error) = result {
+                write_line(&format!("Error: {error}."));
+            }
+        }),
+        Command::Status(None) => chat_consumer.presences().await.map(|mut presences| {
+            presences.sort_by(|a, b| a.user.cmp(&b.user));
+            for presence in presences {
+                write_line(&format!("<{}> - {}", presence.user, presence.status));
+            }
+        }),
+        Command::Seen(user) => chat_consumer
+            .user_presence(user)
+            .await
+            .map(|result| match result {
+                Ok(presence) => write_line(&format_presence(&presence)),
+                Err(error) => write_line(&format!("Error: {error}.")),
+            }),
+        Command::Msg { user, message } => chat_consumer
+            .direct_message(user.clone(), message.clone())
+            .await
+            .map(|result| match result {
+                Ok(()) => write_line(&format!("you -> <{user}>: {message}")),
+                Err(error) => write_line(&format!("Error: {error}.")),
+            }),
+        Command::Edit { id, message } => {
+            chat_consumer.edit_message(id, message).await.map(|result| {
+                if let Err(error) = result {
+                    write_line(&format!("Error: {error}."));
+                }
+            })
+        }
+        Command::Delete(id) => chat_consumer.delete_message(id).await.map(|result| {
+            if let Err(error) = result {
+                write_line(&format!("Error: {error}."));
+            }
+        }),
+        Command::Revisions(id) => chat_consumer
+            .revisions(id)
+            .await
+            .map(|result| match result {
+                Ok(revisions) if revisions.is_empty() => {
+                    write_line(&format!("Message {id} wasn't changed."))
+                }
+                Ok(revisions) => {
+                    for revision in revisions {
+                        write_line(&format_revision(&revision));
+                    }
+                }
+                Err(error) => write_line(&format!("Error: {error}.")),
+            }),
+        Command::React { id, emoji } => chat_consumer.react(id, emoji).await.map(|result| {
+            if let Err(error) = result {
+                write_line(&format!("Error: {error}."));
+            }
+        }),
+        Command::Unreact { id, emoji } => chat_consumer.unreact(id, emoji).await.map(|result| {
+            if let Err(error) = result {
+                write_line(&format!("Error: {error}."));
+            }
+        }),
+        Command::Fibonacci(number) => {
+            write_line(&format!("Calculating fibonacci({number})..."));
+            match worker_consumer.fibonacci(number).await {
+                Ok(result) => write_line(&format!("fibonacci({number}) = {result}")),
+                Err(error) => write_line(&format!(
+                    "Error occurred while sending message to worker: {error}."
+                )),
+            }
+            Ok(())
+        }
+        Command::Factorial(number) => {
+            write_line(&format!("Calculating {number}!..."));
+            match worker_consumer.factorial(number).await {
+                Ok(result) => write_line(&format!("{number}! = {result}")),
+                Err(error) => write_line(&format!(
+                    "Error occurred while sending message to worker: {error}."
+                )),
+            }
+            Ok(())
+        }
+        Command::Kick { user, reason } => {
+            chat_consumer
+                .kick(user.clone(), reason)
+                .await
+                .map(|result| match result {
+                    Ok(()) => write_line(&format!("Kicked <{user}>.")),
+                    Err(error) => write_line(&format!("Error: {error}.")),
+                })
+        }
+        Command::Ban {
+            user,
+            scope,
+            duration,
+            reason,
+        } => chat_consumer
+            .ban(user.clone(), scope, duration, reason)
+            .await
+            .map(|result| match result {
+                Ok(()) => write_line(&format!("Banned <{user}>.")),
+                Err(error) => write_line(&format!("Error: {error}.")),
+            }),
+        Command::Unban(target) => {
+            chat_consumer
+                .unban(target.clone())
+                .await
+                .map(|result| match result {
+                    Ok(()) => write_line(&format!("Lifted ban of {target}.")),
+                    Err(error) => write_line(&format!("Error: {error}.")),
+                })
+        }
+        Command::Mute { user, duration } => {
+            chat_consumer
+                .mute(user.clone(), duration)
+                .await
+                .map(|result| match result {
+                    Ok(()) => write_line(&format!("Muted <{user}>.")),
+                    Err(error) => write_line(&format!("Error: {error}.")),
+                })
+        }
+        Command::Unmute(user) => {
+            chat_consumer
+                .unmute(user.clone())
+                .await
+                .map(|result| match result {
+                    Ok(()) => write_line(&format!("Unmuted <{user}>.")),
+                    Err(error) => write_line(&format!("Error: {error}.")),
+                })
+        }
+        Command::Notice(message) => chat_consumer.notice(message).await.map(|result| {
+            if let Err(error) = result {
+                write_line(&format!("Error: {error}."));
+            }
+        }),
+    };
+    if let Err(error) = result {
+        write_line(&format!("Error occurred while sending message: {error}."));
+    }
+}
+
+/// Print last [HISTORY_LENGTH] messages sent to room
+/// and keep printing new ones until user leaves it.
+async fn watch_room<Error>(
+    chat_consumer: &api::chat::Consumer<Error>,
+    room: String,
+    write_line: impl Fn(&str) + 'static,
+) where
+    Error: 'static,
+{
+    if let Ok(mut messages) = chat_consumer.messages(room.clone(), true).await {
+        let mut last_id = 0;
+        if let Ok(Ok(history)) = chat_consumer
+            .history(room.clone(), None, HISTORY_LENGTH)
+            .await
+        {
+            for message in history {
+                write_line(&format_message(&message));
+                last_id = message.id;
+            }
+        }
+        spawn(async move {
+            while let Some(event) = messages.next().await {
+                match event {
+                    // skip messages that were sent between subscribing and requesting history
+                    MessageEvent::Message(message) if message.id > last_id => {
+                        write_line(&format_message(&message));
+                    }
+                    MessageEvent::Message(_) => {}
+                    MessageEvent::Edited(message) => write_line(&format_message(&message)),
+                    MessageEvent::Deleted { id } => {
+                        write_line(&format!("[#{room}] (message {id} deleted)"));
+                    }
+                    MessageEvent::Reactions { id, reactions } => {
+                        write_line(&format_reactions_update(&room, id, &reactions));
+                    }
+                    MessageEvent::Gap { missed } => {
+                        write_line(&format!("[#{room}] ({missed} messages missed)"));
+                    }
+                    MessageEvent::UpdatesGap { missed } => write_line(&format!(
+                        "[#{room}] ({missed} message updates missed, some messages may be out of date)"
+                    )),
+                }
+            }
+        });
+    }
+}
+
 #[wasm_bindgen]
 pub fn add_numbers(a: i32, b: i32) -> i32 {
     a + b
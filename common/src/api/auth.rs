@@ -0,0 +1,88 @@
+use std::fmt::{self, Display};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::chat::ChatError;
+
+/// Path of account registration endpoint,
+/// accepting [Credentials] and responding with [Token] or [AuthError].
+pub const REGISTER_PATH: &str = "/auth/register";
+
+/// Path of login endpoint,
+/// accepting [Credentials] and responding with [Token] or [AuthError].
+pub const LOGIN_PATH: &str = "/auth/login";
+
+/// Minimum length of account password (in characters).
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Body of registration and login requests.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Credentials {
+    /// Account name, also used as user name in chat.
+    pub name: String,
+    pub password: String,
+}
+
+/// Body of successful registration and login responses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Token {
+    /// Signed bearer token to present when connecting to `/ws`, either in
+    /// `Authorization: Bearer <token>` header or in `token` query parameter
+    /// (for clients unable to set headers, like browsers).
+    pub token: String,
+}
+
+/// Reason why authentication failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuthError {
+    /// Account name is not a valid user name.
+    InvalidName(ChatError),
+
+    /// Password is shorter than [MIN_PASSWORD_LENGTH] characters.
+    PasswordTooShort,
+
+    /// Account with given name is already registered.
+    AccountExists,
+
+    /// Unknown account name or wrong password.
+    InvalidCredentials,
+
+    /// Connection request didn't contain token.
+    MissingToken,
+
+    /// Token is malformed, has invalid signature or has expired.
+    InvalidToken,
+
+    /// Account or address is banned until given time (or permanently if `None`).
+    Banned { until: Option<DateTime<Utc>> },
+
+    /// Server was unable to complete the request (for example to store new account).
+    Internal,
+
+    /// Account name is reserved for admin or moderator account,
+    /// which has to be added by server operator.
+    ReservedName,
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::InvalidName(error) => write!(f, "invalid account name: {error}"),
+            AuthError::PasswordTooShort => write!(
+                f,
+                "password must have at least {MIN_PASSWORD_LENGTH} characters"
+            ),
+            AuthError::AccountExists => write!(f, "account already exists"),
+            AuthError::InvalidCredentials => write!(f, "invalid account name or password"),
+            AuthError::MissingToken => write!(f, "authentication token is missing"),
+            AuthError::InvalidToken => write!(f, "invalid or expired authentication token"),
+            AuthError::Banned { until: Some(until) } => write!(f, "you are banned until {until}"),
+            AuthError::Banned { until: None } => write!(f, "you are banned permanently"),
+            AuthError::Internal => write!(f, "internal server error"),
+            AuthError::ReservedName => write!(f, "account name is reserved"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
@@ -1,22 +1,901 @@
+use std::{
+    fmt::{self, Display},
+    time::Duration,
+};
+
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
 use zzrpc::api;
 
+/// Maximum length of user name (in characters).
+pub const MAX_USER_NAME_LENGTH: usize = 32;
+
+/// Names that can't be chosen by users.
+pub const RESERVED_USER_NAMES: &[&str] = &["admin", "moderator", "server", "system"];
+
+/// Maximum length of room name (in characters).
+pub const MAX_ROOM_NAME_LENGTH: usize = 32;
+
+/// Maximum length of room topic (in characters).
+pub const MAX_TOPIC_LENGTH: usize = 200;
+
+/// Name messages sent by server (like moderation announcements) are attributed to.
+pub const SERVER_USER_NAME: &str = "server";
+
+/// Prefix of body of messages describing action of sender (sent with `/me` command).
+pub const ACTION_PREFIX: &str = "/me ";
+
+/// How often clients should call [Api::heartbeat].
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long typing indicator is shown unless refreshed with [Api::typing_started].
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+
+/// How often clients should call [Api::typing_started] while user keeps typing.
+pub const TYPING_REFRESH_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Maximum length of reaction emoji (in characters).
+pub const MAX_EMOJI_LENGTH: usize = 8;
+
+/// Name of the room every user joins on connect.
+pub const DEFAULT_ROOM: &str = "general";
+
+/// Maximum number of messages returned by single history request.
+pub const MAX_HISTORY_LIMIT: usize = 100;
+
+/// Chat message sent to a room.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    /// Server-assigned id, unique and increasing in order messages were sent.
+    pub id: u64,
+
+    /// Time server received the message.
+    pub timestamp: DateTime<Utc>,
+
+    /// Room message was sent to.
+    pub room: String,
+
+    /// Id of user who sent the message.
+    pub sender_id: usize,
+
+    /// Name sender had at the time of sending the message.
+    pub sender_name: String,
+
+    /// Message text (empty if message was deleted).
+    pub body: String,
+
+    /// Id of first message of thread this message replies to, `None` if it isn't a reply.
+    #[serde(default)]
+    pub reply_to: Option<u64>,
+
+    /// Time message was last edited, `None` if it wasn't.
+    #[serde(default)]
+    pub edited: Option<DateTime<Utc>>,
+
+    /// Whether message was deleted.
+    #[serde(default)]
+    pub deleted: bool,
+
+    /// Reactions to message, filled in by server when message is fetched.
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+}
+
+impl Display for ChatMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.deleted {
+            return write!(f, "[#{}] <{}> (deleted)", self.room, self.sender_name);
+        }
+        match self.body.strip_prefix(ACTION_PREFIX) {
+            Some(action) => write!(f, "[#{}] * {} {action}", self.room, self.sender_name)?,
+            None => write!(f, "[#{}] <{}> {}", self.room, self.sender_name, self.body)?,
+        }
+        if self.edited.is_some() {
+            write!(f, " (edited)")?;
+        }
+        if !self.reactions.is_empty() {
+            write!(f, " ({})", format_reactions(&self.reactions))?;
+        }
+        Ok(())
+    }
+}
+
+/// Format message with its id, replies reference first message of their thread,
+/// like "12:30 [43] ↳ [42] [#general] <Bob> Hi".
+pub fn format_message(message: &ChatMessage) -> String {
+    let time = message.timestamp.with_timezone(&Local).format("%H:%M");
+    match message.reply_to {
+        Some(parent) => format!("{time} [{}] ↳ [{parent}] {message}", message.id),
+        None => format!("{time} [{}] {message}", message.id),
+    }
+}
+
+/// Number of users who reacted to message with given emoji.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: usize,
+}
+
+impl Display for Reaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.emoji, self.count)
+    }
+}
+
+/// Format reactions as comma-separated list, like "👍 2, 🎉 1".
+pub fn format_reactions(reactions: &[Reaction]) -> String {
+    reactions
+        .iter()
+        .map(Reaction::to_string)
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Format change of reactions to message, like "[#general] (reactions to message 42: 👍 2)".
+pub fn format_reactions_update(room: &str, id: u64, reactions: &[Reaction]) -> String {
+    if reactions.is_empty() {
+        format!("[#{room}] (reactions to message {id} removed)")
+    } else {
+        format!(
+            "[#{room}] (reactions to message {id}: {})",
+            format_reactions(reactions)
+        )
+    }
+}
+
+/// Edit or deletion of message, see [Api::revisions].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Revision {
+    pub message_id: u64,
+
+    /// Time message was changed.
+    pub timestamp: DateTime<Utc>,
+
+    /// Name of user who changed the message.
+    pub editor: String,
+
+    /// Body of message before the change.
+    pub previous_body: String,
+
+    /// New body of message, `None` if message was deleted.
+    pub body: Option<String>,
+}
+
+impl Revision {
+    /// Apply change to message.
+    pub fn apply(&self, message: &mut ChatMessage) {
+        match &self.body {
+            Some(body) => {
+                message.body = body.clone();
+                message.edited = Some(self.timestamp);
+            }
+            None => {
+                message.body.clear();
+                message.deleted = true;
+            }
+        }
+    }
+}
+
+/// Format edit or deletion of message, like "12:30 <Alice> edited: Hello -> Hi".
+pub fn format_revision(revision: &Revision) -> String {
+    let time = revision.timestamp.with_timezone(&Local).format("%H:%M");
+    match &revision.body {
+        Some(body) => format!(
+            "{time} <{}> edited: {} -> {body}",
+            revision.editor, revision.previous_body
+        ),
+        None => format!(
+            "{time} <{}> deleted: {}",
+            revision.editor, revision.previous_body
+        ),
+    }
+}
+
+/// Item of room message stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageEvent {
+    /// New message.
+    Message(ChatMessage),
+
+    /// Message was edited, contains its new version.
+    Edited(ChatMessage),
+
+    /// Message with given id was deleted.
+    Deleted { id: u64 },
+
+    /// Reactions to message with given id changed, contains all its reactions.
+    Reactions { id: u64, reactions: Vec<Reaction> },
+
+    /// Subscriber fell behind and given number of messages was skipped.
+    ///
+    /// Never sent when stream was requested with backfill enabled
+    /// (missed messages are sent instead).
+    Gap { missed: u64 },
+
+    /// Subscriber fell behind and given number of edits, deletions and reaction changes
+    /// was skipped, messages received earlier may be out of date.
+    UpdatesGap { missed: u64 },
+}
+
+/// Event originating from server, see [Api::events].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ServerEvent {
+    /// User connected to server.
+    UserJoined { user: String },
+
+    /// User disconnected from server (and its session expired).
+    UserLeft { user: String },
+
+    /// User changed their name.
+    UserRenamed { old_name: String, new_name: String },
+
+    /// User created new room.
+    RoomCreated { room: String, by: String },
+
+    /// Topic of room changed (empty topic means it was cleared).
+    TopicChanged {
+        room: String,
+        topic: String,
+        by: String,
+    },
+
+    /// Message from server administrators to all users.
+    Notice { message: String, by: String },
+
+    /// Server is shutting down and will close all connections in given number of seconds.
+    ShutdownWarning { seconds: u64 },
+
+    /// User was kicked by moderator with given name (with optional reason),
+    /// only sent to that user right before its connection is closed.
+    Kicked { by: String, reason: String },
+
+    /// User was banned by admin with given name until given time (or permanently if `None`),
+    /// only sent to that user right before its connection is closed.
+    Banned {
+        by: String,
+        until: Option<DateTime<Utc>>,
+        reason: String,
+    },
+
+    /// Subscriber fell behind and given number of events was skipped.
+    Gap { missed: u64 },
+}
+
+impl Display for ServerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerEvent::UserJoined { user } => write!(f, "New user connected: <{user}>."),
+            ServerEvent::UserLeft { user } => write!(f, "User <{user}> left."),
+            ServerEvent::UserRenamed { old_name, new_name } => {
+                write!(f, "User <{old_name}> is now known as <{new_name}>.")
+            }
+            ServerEvent::RoomCreated { room, by } => write!(f, "User <{by}> created room #{room}."),
+            ServerEvent::TopicChanged { room, topic, by } if topic.is_empty() => {
+                write!(f, "User <{by}> cleared topic of #{room}.")
+            }
+            ServerEvent::TopicChanged { room, topic, by } => {
+                write!(f, "User <{by}> changed topic of #{room} to: {topic}")
+            }
+            ServerEvent::Notice { message, by } => write!(f, "Notice from <{by}>: {message}"),
+            ServerEvent::ShutdownWarning { seconds: 1 } => {
+                write!(f, "Server is going down in 1 second.")
+            }
+            ServerEvent::ShutdownWarning { seconds } => {
+                write!(f, "Server is going down in {seconds} seconds.")
+            }
+            ServerEvent::Kicked { by, reason } => {
+                write!(f, "You were kicked by <{by}>{}.", format_reason(reason))
+            }
+            ServerEvent::Banned {
+                by,
+                until: Some(until),
+                reason,
+            } => write!(
+                f,
+                "You were banned by <{by}> until {until}{}.",
+                format_reason(reason)
+            ),
+            ServerEvent::Banned {
+                by,
+                until: None,
+                reason,
+            } => write!(
+                f,
+                "You were banned by <{by}> permanently{}.",
+                format_reason(reason)
+            ),
+            ServerEvent::Gap { missed } => write!(f, "({missed} server events missed)"),
+        }
+    }
+}
+
+/// Format optional reason of moderation action to be appended to its description.
+fn format_reason(reason: &str) -> String {
+    if reason.is_empty() {
+        String::new()
+    } else {
+        format!(": {reason}")
+    }
+}
+
+/// Availability of user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PresenceStatus {
+    #[default]
+    Online,
+    /// Set by user.
+    Away,
+    /// Set by server when user is inactive for a while, cleared on activity.
+    Idle,
+    /// Set by user.
+    DoNotDisturb,
+    /// Connection was lost, session can still be resumed.
+    Offline,
+}
+
+impl Display for PresenceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PresenceStatus::Online => write!(f, "online"),
+            PresenceStatus::Away => write!(f, "away"),
+            PresenceStatus::Idle => write!(f, "idle"),
+            PresenceStatus::DoNotDisturb => write!(f, "do not disturb"),
+            PresenceStatus::Offline => write!(f, "offline"),
+        }
+    }
+}
+
+/// Presence of user, see [Api::presence].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Presence {
+    pub user: String,
+    pub status: PresenceStatus,
+    /// Last time user was active (sent a message or reported activity with heartbeat),
+    /// or disconnected.
+    pub last_seen: DateTime<Utc>,
+}
+
+impl Display for Presence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.status {
+            PresenceStatus::DoNotDisturb => {
+                write!(f, "User <{}> doesn't want to be disturbed.", self.user)
+            }
+            PresenceStatus::Offline => write!(f, "User <{}> went offline.", self.user),
+            status => write!(f, "User <{}> is {status}.", self.user),
+        }
+    }
+}
+
+/// Format presence with time user was last seen, like "<Alice>: away, last seen 12:30.".
+pub fn format_presence(presence: &Presence) -> String {
+    let last_seen = presence.last_seen.with_timezone(&Local);
+    let time = if last_seen.date_naive() == Local::now().date_naive() {
+        last_seen.format("%H:%M")
+    } else {
+        last_seen.format("%Y-%m-%d %H:%M")
+    };
+    format!(
+        "<{}>: {}, last seen {time}.",
+        presence.user, presence.status
+    )
+}
+
+/// Change of typing indicator of user in room, see [Api::typing].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Typing {
+    pub room: String,
+    pub user: String,
+    /// Whether user started (or stopped) typing.
+    pub typing: bool,
+}
+
+impl Display for Typing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.typing {
+            write!(f, "<{}> is typing in #{}...", self.user, self.room)
+        } else {
+            write!(f, "<{}> stopped typing in #{}.", self.user, self.room)
+        }
+    }
+}
+
+/// Session information.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub user_id: usize,
+    pub user_name: String,
+
+    /// Secret token used to resume session after reconnecting.
+    pub token: String,
+}
+
+/// User's privileges, ordered from lowest to highest.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum Role {
+    #[default]
+    User,
+
+    /// Can kick and mute users.
+    Moderator,
+
+    /// Can also ban users.
+    Admin,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::User => write!(f, "user"),
+            Role::Moderator => write!(f, "moderator"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// What ban applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BanScope {
+    /// Account user authenticated with.
+    Account,
+
+    /// Address user is connected from.
+    Address,
+}
+
+/// Reason why chat operation failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChatError {
+    /// User name is empty.
+    EmptyName,
+
+    /// User name is longer than [MAX_USER_NAME_LENGTH] characters.
+    NameTooLong,
+
+    /// User name contains character other than letters, digits, spaces, `_` or `-`,
+    /// or starts/ends with a space.
+    InvalidNameCharacter,
+
+    /// User name is reserved.
+    ReservedName,
+
+    /// User name is already used by other user.
+    NameTaken,
+
+    /// Room name is empty, longer than [MAX_ROOM_NAME_LENGTH] characters
+    /// or contains character other than letters, digits, `_` or `-`.
+    InvalidRoomName,
+
+    /// Room topic is longer than [MAX_TOPIC_LENGTH] characters.
+    TopicTooLong,
+
+    /// Room with given name already exists.
+    RoomExists,
+
+    /// Room with given name doesn't exist.
+    UnknownRoom,
+
+    /// User is not a member of the room.
+    NotMember,
+
+    /// Recipient of direct message is not connected.
+    UnknownUser,
+
+    /// Session token is invalid or session has expired.
+    InvalidSessionToken,
+
+    /// Session is still in use by another connection.
+    SessionInUse,
+
+    /// Session of user was closed (user was kicked or banned).
+    SessionClosed,
+
+    /// Message is longer than `max_length` characters.
+    MessageTooLong { max_length: usize },
+
+    /// Sender (or other users connected from the same address)
+    /// sent too many messages in short time.
+    RateLimited,
+
+    /// User is not allowed to perform the operation.
+    NotAuthorized,
+
+    /// User was muted by moderator and can't send messages.
+    Muted,
+
+    /// Status can only be set by server.
+    InvalidStatus,
+
+    /// Message doesn't exist or was deleted.
+    UnknownMessage,
+
+    /// Reaction is empty, longer than [MAX_EMOJI_LENGTH] characters
+    /// or contains ASCII or whitespace characters.
+    InvalidEmoji,
+}
+
+impl Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatError::EmptyName => write!(f, "name cannot be empty"),
+            ChatError::NameTooLong => write!(
+                f,
+                "name cannot be longer than {MAX_USER_NAME_LENGTH} characters"
+            ),
+            ChatError::InvalidNameCharacter => write!(
+                f,
+                "name can contain only letters, digits, spaces, '_' and '-' \
+                and cannot start or end with a space"
+            ),
+            ChatError::ReservedName => write!(f, "name is reserved"),
+            ChatError::NameTaken => write!(f, "name is already taken"),
+            ChatError::InvalidRoomName => write!(
+                f,
+                "room name must have between 1 and {MAX_ROOM_NAME_LENGTH} characters \
+                and can contain only letters, digits, '_' and '-'"
+            ),
+            ChatError::TopicTooLong => write!(
+                f,
+                "topic cannot be longer than {MAX_TOPIC_LENGTH} characters"
+            ),
+            ChatError::RoomExists => write!(f, "room already exists"),
+            ChatError::UnknownRoom => write!(f, "room doesn't exist"),
+            ChatError::NotMember => write!(f, "you are not a member of the room"),
+            ChatError::UnknownUser => write!(f, "user is not connected"),
+            ChatError::InvalidSessionToken => write!(f, "invalid or expired session token"),
+            ChatError::SessionInUse => write!(f, "session is already in use"),
+            ChatError::SessionClosed => write!(f, "your session was closed"),
+            ChatError::MessageTooLong { max_length } => {
+                write!(f, "message cannot be longer than {max_length} characters")
+            }
+            ChatError::RateLimited => write!(f, "you are sending messages too fast"),
+            ChatError::NotAuthorized => write!(f, "you are not allowed to do that"),
+            ChatError::Muted => write!(f, "you are muted"),
+            ChatError::InvalidStatus => write!(f, "status can't be set manually"),
+            ChatError::UnknownMessage => write!(f, "message doesn't exist"),
+            ChatError::InvalidEmoji => write!(f, "reaction must be an emoji"),
+        }
+    }
+}
+
+impl std::error::Error for ChatError {}
+
+/// Format user names as comma-separated list, like "<Alice>, <Bob>".
+pub fn format_user_names(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("<{name}>"))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Check if user name is well-formed (doesn't check if it is already taken).
+pub fn validate_user_name(name: &str) -> Result<(), ChatError> {
+    if name.is_empty() {
+        return Err(ChatError::EmptyName);
+    }
+    if name.chars().count() > MAX_USER_NAME_LENGTH {
+        return Err(ChatError::NameTooLong);
+    }
+    if name.starts_with(' ')
+        || name.ends_with(' ')
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == ' ' || c == '_' || c == '-')
+    {
+        return Err(ChatError::InvalidNameCharacter);
+    }
+    if RESERVED_USER_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(name))
+    {
+        return Err(ChatError::ReservedName);
+    }
+    Ok(())
+}
+
+/// Check if room name is well-formed.
+pub fn validate_room_name(name: &str) -> Result<(), ChatError> {
+    let length = name.chars().count();
+    if length == 0
+        || length > MAX_ROOM_NAME_LENGTH
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    {
+        Err(ChatError::InvalidRoomName)
+    } else {
+        Ok(())
+    }
+}
+
+/// Check if reaction is well-formed.
+pub fn validate_emoji(emoji: &str) -> Result<(), ChatError> {
+    let length = emoji.chars().count();
+    if length == 0
+        || length > MAX_EMOJI_LENGTH
+        || emoji
+            .chars()
+            .any(|c| c.is_ascii() || c.is_whitespace() || c.is_control())
+    {
+        Err(ChatError::InvalidEmoji)
+    } else {
+        Ok(())
+    }
+}
+
 #[api]
 pub trait Api {
     /// Init and request user name.
-    async fn user_name(&self) -> String;
+    ///
+    /// Connection of user is announced to others once this method
+    /// or [Api::session] is called.
+    async fn user_name(&self) -> Result<String, ChatError>;
+
+    /// Get user id.
+    async fn user_id(&self) -> usize;
+
+    /// Get session information, including token that can be used
+    /// to resume the session after reconnecting.
+    async fn session(&self) -> Result<Session, ChatError>;
+
+    /// Resume session after reconnecting and return pairs containing: (room name, event)
+    /// with messages sent to user's rooms after message with id `last_seen_message_id`.
+    ///
+    /// At most [MAX_HISTORY_LIMIT] latest messages are returned per room,
+    /// [MessageEvent::Gap] (sent before messages) reports skipped earlier messages.
+    ///
+    /// Should be called right after connecting, before subscribing to any streams
+    /// (user created for the new connection is discarded).
+    async fn resume(
+        &self,
+        token: String,
+        last_seen_message_id: u64,
+    ) -> Result<Vec<(String, MessageEvent)>, ChatError>;
 
     /// Get connected user names.
     async fn user_names(&self) -> Vec<String>;
 
-    /// Send chat message.
-    async fn message(&self, message: String);
+    /// Change user name.
+    async fn set_user_name(&self, name: String) -> Result<(), ChatError>;
+
+    /// Get names of existing rooms.
+    async fn rooms(&self) -> Vec<String>;
+
+    /// Create new room and join it.
+    async fn create_room(&self, room: String) -> Result<(), ChatError>;
+
+    /// Join existing room.
+    async fn join_room(&self, room: String) -> Result<(), ChatError>;
+
+    /// Leave room.
+    async fn leave_room(&self, room: String) -> Result<(), ChatError>;
+
+    /// Get topic of room (empty if not set).
+    async fn topic(&self, room: String) -> Result<String, ChatError>;
+
+    /// Set topic of room user is member of (empty topic clears it).
+    async fn set_topic(&self, room: String, topic: String) -> Result<(), ChatError>;
+
+    /// Get names of users in room.
+    async fn room_members(&self, room: String) -> Result<Vec<String>, ChatError>;
+
+    /// Send chat message to room, optionally as a reply to message with id `reply_to`
+    /// sent to the same room.
+    ///
+    /// Replies to replies are added to the thread of the original message.
+    async fn message(
+        &self,
+        room: String,
+        message: String,
+        reply_to: Option<u64>,
+    ) -> Result<(), ChatError>;
+
+    /// Change body of message with given id.
+    ///
+    /// Only user who sent the message (authenticated with the same account)
+    /// and moderators can edit it, room members receive [MessageEvent::Edited].
+    async fn edit_message(&self, message_id: u64, message: String) -> Result<(), ChatError>;
+
+    /// Delete message with given id.
+    ///
+    /// Only user who sent the message (authenticated with the same account)
+    /// and moderators can delete it, room members receive [MessageEvent::Deleted].
+    async fn delete_message(&self, message_id: u64) -> Result<(), ChatError>;
+
+    /// Get edits and deletions of message with given id, oldest first (moderators only).
+    async fn revisions(&self, message_id: u64) -> Result<Vec<Revision>, ChatError>;
+
+    /// React to message with given id.
+    ///
+    /// Every account can react with given emoji once, room members receive
+    /// [MessageEvent::Reactions] with updated counts.
+    async fn react(&self, message_id: u64, emoji: String) -> Result<(), ChatError>;
+
+    /// Remove reaction to message with given id.
+    async fn unreact(&self, message_id: u64, emoji: String) -> Result<(), ChatError>;
+
+    /// Get at most `limit` (capped at [MAX_HISTORY_LIMIT]) latest messages sent to room
+    /// before message with id `before` (or latest messages if `before` is `None`), oldest first.
+    async fn history(
+        &self,
+        room: String,
+        before: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<ChatMessage>, ChatError>;
+
+    /// Get thread containing message with given id: the first message of thread
+    /// followed by all replies to it, oldest first.
+    async fn thread(&self, message_id: u64) -> Result<Vec<ChatMessage>, ChatError>;
+
+    /// Stream of messages sent to room.
+    ///
+    /// If subscriber falls behind and `backfill` is set, missed messages
+    /// are loaded from history, otherwise [MessageEvent::Gap] is sent.
+    /// Skipped edits, deletions and reaction changes are reported by [MessageEvent::UpdatesGap].
+    ///
+    /// Stream ends when user leaves the room,
+    /// it is empty if user isn't a member of the room.
+    async fn messages(&self, room: String, backfill: bool) -> impl Stream<Item = MessageEvent>;
+
+    /// Send private message to user with given name.
+    async fn direct_message(&self, to: String, message: String) -> Result<(), ChatError>;
+
+    /// Stream of pairs containing: (sender name, message) sent privately to user.
+    async fn inbox(&self) -> impl Stream<Item = (String, String)>;
+
+    /// Stream of names of users joining room.
+    async fn joined(&self, room: String) -> impl Stream<Item = String>;
+
+    /// Stream of names of users leaving room.
+    async fn left(&self, room: String) -> impl Stream<Item = String>;
 
-    /// Stream of pairs containing: (user name, message)
-    async fn messages(&self) -> impl Stream<Item = (String, String)>;
+    /// Stream of events originating from server.
+    ///
+    /// Events about connecting and disconnecting of user itself are not sent,
+    /// events skipped by lagging subscriber are reported by [ServerEvent::Gap].
+    async fn events(&self) -> impl Stream<Item = ServerEvent>;
+
+    /// Send notice to all connected users (requires [Role::Admin]).
+    async fn notice(&self, message: String) -> Result<(), ChatError>;
 
     /// Stream of names of newly connected users.
     async fn connected(&self) -> impl Stream<Item = String>;
 
     /// Stream of names of disconnected users.
     async fn disconnected(&self) -> impl Stream<Item = String>;
+
+    /// Stream of pairs containing: (old user name, new user name)
+    async fn renamed(&self) -> impl Stream<Item = (String, String)>;
+
+    /// Report that client is still running, `active` should be set
+    /// if user interacted with it since last heartbeat.
+    ///
+    /// Should be called every [HEARTBEAT_INTERVAL], user is marked as [PresenceStatus::Idle]
+    /// when there is no activity for a while.
+    async fn heartbeat(&self, active: bool);
+
+    /// Set status of user ([PresenceStatus::Online], [PresenceStatus::Away]
+    /// or [PresenceStatus::DoNotDisturb]).
+    async fn set_status(&self, status: PresenceStatus) -> Result<(), ChatError>;
+
+    /// Get presence of user with given name, which can also be a user
+    /// who already left (with [PresenceStatus::Offline] status).
+    async fn user_presence(&self, user: String) -> Result<Presence, ChatError>;
+
+    /// Get presence of all users, users whose connection was lost are reported
+    /// as [PresenceStatus::Offline] until their session expires.
+    async fn presences(&self) -> Vec<Presence>;
+
+    /// Stream of changes of presence of connected users.
+    ///
+    /// Users connecting and leaving are reported by [Api::events].
+    /// If subscriber falls behind, presence of all users is sent again.
+    async fn presence(&self) -> impl Stream<Item = Presence>;
+
+    /// Report that user is typing message to room.
+    ///
+    /// Should be repeated every [TYPING_REFRESH_INTERVAL] while user keeps typing,
+    /// indicator expires after [TYPING_TIMEOUT]. Sending a message to the room clears it.
+    async fn typing_started(&self, room: String) -> Result<(), ChatError>;
+
+    /// Report that user stopped typing message to room without sending it.
+    async fn typing_stopped(&self, room: String);
+
+    /// Stream of changes of typing indicators of other users in rooms user is member of.
+    ///
+    /// If subscriber falls behind, indicators of all members of those rooms are sent again.
+    async fn typing(&self) -> impl Stream<Item = Typing>;
+
+    /// Get role of user.
+    async fn role(&self) -> Result<Role, ChatError>;
+
+    /// Disconnect user with given name (requires [Role::Moderator]).
+    ///
+    /// Kick is announced to rooms user was member of, the user itself receives
+    /// [ServerEvent::Kicked] and its session can't be resumed.
+    async fn kick(&self, user: String, reason: String) -> Result<(), ChatError>;
+
+    /// Ban account of user with given name or address user is connected from
+    /// for given duration (or permanently if `None`) and disconnect all matching users
+    /// (requires [Role::Admin]).
+    ///
+    /// Ban is announced to rooms banned users were members of, banned users
+    /// receive [ServerEvent::Banned].
+    async fn ban(
+        &self,
+        user: String,
+        scope: BanScope,
+        duration: Option<Duration>,
+        reason: String,
+    ) -> Result<(), ChatError>;
+
+    /// Lift ban of account with given name or given IP address (requires [Role::Admin]).
+    async fn unban(&self, target: String) -> Result<(), ChatError>;
+
+    /// Prevent user with given name from sending messages for given duration
+    /// (or until unmuted if `None`) (requires [Role::Moderator]).
+    ///
+    /// Mute is announced to rooms user is member of.
+    async fn mute(&self, user: String, duration: Option<Duration>) -> Result<(), ChatError>;
+
+    /// Allow muted user with given name to send messages again (requires [Role::Moderator]).
+    async fn unmute(&self, user: String) -> Result<(), ChatError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_emoji, validate_room_name, validate_user_name, ChatError};
+
+    #[test]
+    fn test_validate_user_name() {
+        assert_eq!(validate_user_name("Alice"), Ok(()));
+        assert_eq!(validate_user_name("bob_the-builder 2"), Ok(()));
+        assert_eq!(validate_user_name(""), Err(ChatError::EmptyName));
+        assert_eq!(
+            validate_user_name(&"a".repeat(33)),
+            Err(ChatError::NameTooLong)
+        );
+        assert_eq!(
+            validate_user_name(" Alice"),
+            Err(ChatError::InvalidNameCharacter)
+        );
+        assert_eq!(
+            validate_user_name("<Alice>"),
+            Err(ChatError::InvalidNameCharacter)
+        );
+        assert_eq!(validate_user_name("Admin"), Err(ChatError::ReservedName));
+    }
+
+    #[test]
+    fn test_validate_room_name() {
+        assert_eq!(validate_room_name("general"), Ok(()));
+        assert_eq!(validate_room_name("rust_and-wasm"), Ok(()));
+        assert_eq!(validate_room_name(""), Err(ChatError::InvalidRoomName));
+        assert_eq!(
+            validate_room_name("two words"),
+            Err(ChatError::InvalidRoomName)
+        );
+        assert_eq!(
+            validate_room_name(&"a".repeat(33)),
+            Err(ChatError::InvalidRoomName)
+        );
+    }
+
+    #[test]
+    fn test_validate_emoji() {
+        assert_eq!(validate_emoji("👍"), Ok(()));
+        assert_eq!(validate_emoji("👩‍💻"), Ok(()));
+        assert_eq!(validate_emoji(""), Err(ChatError::InvalidEmoji));
+        assert_eq!(validate_emoji(":)"), Err(ChatError::InvalidEmoji));
+        assert_eq!(validate_emoji("👍 👍"), Err(ChatError::InvalidEmoji));
+        assert_eq!(
+            validate_emoji(&"🎉".repeat(9)),
+            Err(ChatError::InvalidEmoji)
+        );
+    }
 }
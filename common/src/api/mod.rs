@@ -1,3 +1,4 @@
+pub mod auth;
 pub mod chat;
 #[cfg(feature = "worker")]
 pub mod worker;
@@ -0,0 +1,708 @@
+use std::{
+    fmt::{self, Display},
+    time::Duration,
+};
+
+use crate::api::chat::{
+    validate_emoji, validate_room_name, validate_user_name, BanScope, PresenceStatus, ACTION_PREFIX,
+};
+
+/// Command entered by user in chat input, see [Command::parse].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Message to send to current room.
+    Message(String),
+
+    /// `/me <action>`
+    Me(String),
+
+    /// `/help [command]`
+    Help(Option<&'static CommandHelp>),
+
+    /// `/nick <name>`
+    Nick(String),
+
+    /// `/rooms`
+    Rooms,
+
+    /// `/create <room>`
+    Create(String),
+
+    /// `/join <room>`
+    Join(String),
+
+    /// `/leave`
+    Leave,
+
+    /// `/topic [topic]`, shows topic of current room if `None`,
+    /// empty topic clears it.
+    Topic(Option<String>),
+
+    /// `/who [room]`, lists members of given room (or current room if `None`).
+    Who(Option<String>),
+
+    /// `/status [online|away|dnd]`, lists statuses of connected users if `None`.
+    Status(Option<PresenceStatus>),
+
+    /// `/seen <user>`
+    Seen(String),
+
+    /// `/msg <user> <message>`
+    Msg { user: String, message: String },
+
+    /// `/reply <id> <message>`
+    Reply { id: u64, message: String },
+
+    /// `/thread <id>`
+    Thread(u64),
+
+    /// `/edit <id> <message>`
+    Edit { id: u64, message: String },
+
+    /// `/delete <id>`
+    Delete(u64),
+
+    /// `/revisions <id>`
+    Revisions(u64),
+
+    /// `/react <id> <emoji>`
+    React { id: u64, emoji: String },
+
+    /// `/unreact <id> <emoji>`
+    Unreact { id: u64, emoji: String },
+
+    /// `/fib <n>`
+    Fibonacci(u64),
+
+    /// `/fact <n>`
+    Factorial(u64),
+
+    /// `/kick <user> [reason]`
+    Kick { user: String, reason: String },
+
+    /// `/ban <user> [duration] [reason]` or `/banip <user> [duration] [reason]`,
+    /// ban is permanent if duration is `None`.
+    Ban {
+        user: String,
+        scope: BanScope,
+        duration: Option<Duration>,
+        reason: String,
+    },
+
+    /// `/unban <account or IP address>`
+    Unban(String),
+
+    /// `/mute <user> [duration]`, user is muted until unmuted if duration is `None`.
+    Mute {
+        user: String,
+        duration: Option<Duration>,
+    },
+
+    /// `/unmute <user>`
+    Unmute(String),
+
+    /// `/notice <message>`
+    Notice(String),
+}
+
+/// Usage and description of command.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CommandHelp {
+    /// Command name, without leading `/`.
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub description: &'static str,
+}
+
+impl Display for CommandHelp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} - {}", self.usage, self.description)
+    }
+}
+
+/// All available commands.
+pub const COMMANDS: &[CommandHelp] = &[
+    CommandHelp {
+        name: "help",
+        usage: "/help [command]",
+        description: "list commands or show usage of given command",
+    },
+    CommandHelp {
+        name: "nick",
+        usage: "/nick <name>",
+        description: "change your name",
+    },
+    CommandHelp {
+        name: "rooms",
+        usage: "/rooms",
+        description: "list rooms",
+    },
+    CommandHelp {
+        name: "create",
+        usage: "/create <room>",
+        description: "create a room and join it",
+    },
+    CommandHelp {
+        name: "join",
+        usage: "/join <room>",
+        description: "join a room",
+    },
+    CommandHelp {
+        name: "leave",
+        usage: "/leave",
+        description: "leave current room",
+    },
+    CommandHelp {
+        name: "topic",
+        usage: "/topic [topic]",
+        description: "show or set topic of current room ('/topic -' clears it)",
+    },
+    CommandHelp {
+        name: "who",
+        usage: "/who [room]",
+        description: "list members of current (or given) room",
+    },
+    CommandHelp {
+        name: "status",
+        usage: "/status [online|away|dnd]",
+        description: "set your status or list statuses of connected users",
+    },
+    CommandHelp {
+        name: "seen",
+        usage: "/seen <user>",
+        description: "show status of user and when they were last active",
+    },
+    CommandHelp {
+        name: "msg",
+        usage: "/msg <user> <message>",
+        description: "send a private message (enclose names with spaces in '<>')",
+    },
+    CommandHelp {
+        name: "me",
+        usage: "/me <action>",
+        description: "describe what you are doing",
+    },
+    CommandHelp {
+        name: "reply",
+        usage: "/reply <id> <message>",
+        description: "reply to message with given id in current room",
+    },
+    CommandHelp {
+        name: "thread",
+        usage: "/thread <id>",
+        description: "show message with given id and all replies to it",
+    },
+    CommandHelp {
+        name: "edit",
+        usage: "/edit <id> <message>",
+        description: "change your message with given id (shown in brackets before it)",
+    },
+    CommandHelp {
+        name: "delete",
+        usage: "/delete <id>",
+        description: "delete your message with given id",
+    },
+    CommandHelp {
+        name: "react",
+        usage: "/react <id> <emoji>",
+        description: "react to message with given id",
+    },
+    CommandHelp {
+        name: "unreact",
+        usage: "/unreact <id> <emoji>",
+        description: "remove your reaction to message",
+    },
+    CommandHelp {
+        name: "fib",
+        usage: "/fib <n>",
+        description: "calculate n-th element of Fibonacci sequence",
+    },
+    CommandHelp {
+        name: "fact",
+        usage: "/fact <n>",
+        description: "calculate factorial of n",
+    },
+    CommandHelp {
+        name: "kick",
+        usage: "/kick <user> [reason]",
+        description: "disconnect user (moderators only)",
+    },
+    CommandHelp {
+        name: "mute",
+        usage: "/mute <user> [duration]",
+        description: "prevent user from sending messages, e.g. '/mute Bob 10m' (moderators only)",
+    },
+    CommandHelp {
+        name: "unmute",
+        usage: "/unmute <user>",
+        description: "allow muted user to send messages again (moderators only)",
+    },
+    CommandHelp {
+        name: "revisions",
+        usage: "/revisions <id>",
+        description: "show edit history of message (moderators only)",
+    },
+    CommandHelp {
+        name: "ban",
+        usage: "/ban <user> [duration] [reason]",
+        description: "ban user's account, permanently if duration is omitted (admins only)",
+    },
+    CommandHelp {
+        name: "banip",
+        usage: "/banip <user> [duration] [reason]",
+        description: "ban address user is connected from (admins only)",
+    },
+    CommandHelp {
+        name: "unban",
+        usage: "/unban <account or IP address>",
+        description: "lift a ban (admins only)",
+    },
+    CommandHelp {
+        name: "notice",
+        usage: "/notice <message>",
+        description: "send a notice to all users (admins only)",
+    },
+];
+
+/// Find help of command with given name (with or without leading `/`).
+pub fn command_help(name: &str) -> Option<&'static CommandHelp> {
+    let name = name.strip_prefix('/').unwrap_or(name);
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+/// Reason why command couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    /// There is no command with given name.
+    Unknown(String),
+
+    /// Command has wrong number of arguments.
+    Usage(&'static CommandHelp),
+
+    /// Argument of command is invalid, contains explanation.
+    InvalidArgument(String),
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Unknown(name) => {
+                write!(f, "unknown command /{name}, type /help to list commands")
+            }
+            CommandError::Usage(help) => write!(f, "usage: {}", help.usage),
+            CommandError::InvalidArgument(explanation) => explanation.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl Command {
+    /// Parse line entered by user.
+    ///
+    /// Lines not starting with `/` are messages, `//` can be used
+    /// to send a message starting with `/`.
+    pub fn parse(line: &str) -> Result<Self, CommandError> {
+        let line = line.trim();
+        if let Some(message) = line.strip_prefix("//") {
+            return Ok(Command::Message(format!("/{message}")));
+        }
+        let Some(line) = line.strip_prefix('/') else {
+            return Ok(Command::Message(line.to_string()));
+        };
+        let (name, arguments) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(name, arguments)| (name, arguments.trim()));
+        let help = command_help(name).ok_or_else(|| CommandError::Unknown(name.to_string()))?;
+        let usage = || CommandError::Usage(help);
+        let command = match name {
+            "help" => match arguments {
+                "" => Command::Help(None),
+                name => Command::Help(Some(
+                    command_help(name).ok_or_else(|| CommandError::Unknown(name.to_string()))?,
+                )),
+            },
+            "nick" => {
+                if arguments.is_empty() {
+                    return Err(usage());
+                }
+                validate_user_name(arguments).map_err(invalid_argument)?;
+                Command::Nick(arguments.to_string())
+            }
+            "rooms" | "leave" if !arguments.is_empty() => return Err(usage()),
+            "rooms" => Command::Rooms,
+            "leave" => Command::Leave,
+            "create" | "join" => {
+                if arguments.is_empty() {
+                    return Err(usage());
+                }
+                let room = arguments.strip_prefix('#').unwrap_or(arguments);
+                validate_room_name(room).map_err(invalid_argument)?;
+                if name == "create" {
+                    Command::Create(room.to_string())
+                } else {
+                    Command::Join(room.to_string())
+                }
+            }
+            "who" => match arguments {
+                "" => Command::Who(None),
+                room => {
+                    let room = room.strip_prefix('#').unwrap_or(room);
+                    validate_room_name(room).map_err(invalid_argument)?;
+                    Command::Who(Some(room.to_string()))
+                }
+            },
+            "topic" => match arguments {
+                "" => Command::Topic(None),
+                "-" => Command::Topic(Some(String::new())),
+                topic => Command::Topic(Some(topic.to_string())),
+            },
+            "status" => match arguments {
+                "" => Command::Status(None),
+                "online" => Command::Status(Some(PresenceStatus::Online)),
+                "away" => Command::Status(Some(PresenceStatus::Away)),
+                "dnd" => Command::Status(Some(PresenceStatus::DoNotDisturb)),
+                _ => return Err(usage()),
+            },
+            "msg" => {
+                let (user, message) = split_user(arguments).ok_or_else(usage)?;
+                if message.is_empty() {
+                    return Err(usage());
+                }
+                Command::Msg {
+                    user,
+                    message: message.to_string(),
+                }
+            }
+            "me" | "notice" => {
+                if arguments.is_empty() {
+                    return Err(usage());
+                }
+                if name == "me" {
+                    Command::Me(arguments.to_string())
+                } else {
+                    Command::Notice(arguments.to_string())
+                }
+            }
+            "fib" | "fact" => {
+                if arguments.is_empty() {
+                    return Err(usage());
+                }
+                let number = arguments.parse::<u64>().map_err(|_| {
+                    CommandError::InvalidArgument(format!(
+                        "{arguments} is not a non-negative integer"
+                    ))
+                })?;
+                if name == "fib" {
+                    Command::Fibonacci(number)
+                } else {
+                    Command::Factorial(number)
+                }
+            }
+            "reply" | "edit" => {
+                let (id, message) = arguments
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(usage)?;
+                let (id, message) = (parse_message_id(id)?, message.trim().to_string());
+                if name == "reply" {
+                    Command::Reply { id, message }
+                } else {
+                    Command::Edit { id, message }
+                }
+            }
+            "react" | "unreact" => {
+                let (id, emoji) = arguments
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(usage)?;
+                let (id, emoji) = (parse_message_id(id)?, emoji.trim().to_string());
+                validate_emoji(&emoji).map_err(invalid_argument)?;
+                if name == "react" {
+                    Command::React { id, emoji }
+                } else {
+                    Command::Unreact { id, emoji }
+                }
+            }
+            "thread" | "delete" | "revisions" => {
+                if arguments.is_empty() {
+                    return Err(usage());
+                }
+                let id = parse_message_id(arguments)?;
+                match name {
+                    "thread" => Command::Thread(id),
+                    "delete" => Command::Delete(id),
+                    _ => Command::Revisions(id),
+                }
+            }
+            "kick" => {
+                let (user, reason) = split_user(arguments).ok_or_else(usage)?;
+                Command::Kick {
+                    user,
+                    reason: reason.to_string(),
+                }
+            }
+            "mute" => {
+                let (user, duration) = split_user(arguments).ok_or_else(usage)?;
+                let duration = match duration {
+                    "" => None,
+                    duration => Some(parse_duration(duration).ok_or_else(|| {
+                        CommandError::InvalidArgument(format!(
+                            "{duration} is not a valid duration, use e.g. 30s, 10m, 1h30m or 7d"
+                        ))
+                    })?),
+                };
+                Command::Mute { user, duration }
+            }
+            "ban" | "banip" => {
+                let (user, rest) = split_user(arguments).ok_or_else(usage)?;
+                let (duration, reason) = match rest.split_once(char::is_whitespace) {
+                    Some((first, reason)) => (parse_duration(first), reason.trim()),
+                    None => (parse_duration(rest), ""),
+                };
+                // first word is part of reason if it isn't a duration
+                let reason = if duration.is_some() { reason } else { rest };
+                Command::Ban {
+                    user,
+                    scope: if name == "ban" {
+                        BanScope::Account
+                    } else {
+                        BanScope::Address
+                    },
+                    duration,
+                    reason: reason.to_string(),
+                }
+            }
+            "seen" | "unmute" | "unban" => {
+                let (user, rest) = split_user(arguments).ok_or_else(usage)?;
+                if !rest.is_empty() {
+                    return Err(usage());
+                }
+                match name {
+                    "seen" => Command::Seen(user),
+                    "unmute" => Command::Unmute(user),
+                    _ => Command::Unban(user),
+                }
+            }
+            _ => unreachable!("command /{name} has help but isn't parsed"),
+        };
+        Ok(command)
+    }
+
+    /// Body of chat message sent for [Command::Me].
+    pub fn action_message(action: &str) -> String {
+        format!("{ACTION_PREFIX}{action}")
+    }
+}
+
+fn invalid_argument(error: impl Display) -> CommandError {
+    CommandError::InvalidArgument(error.to_string())
+}
+
+/// Parse message id, optionally enclosed in `[]` like in displayed messages.
+fn parse_message_id(id: &str) -> Result<u64, CommandError> {
+    id.strip_prefix('[')
+        .and_then(|id| id.strip_suffix(']'))
+        .unwrap_or(id)
+        .parse()
+        .map_err(|_| CommandError::InvalidArgument(format!("{id} is not a valid message id")))
+}
+
+/// Split arguments into user name (optionally enclosed in `<>`) and the rest.
+fn split_user(arguments: &str) -> Option<(String, &str)> {
+    let (user, rest) = if let Some(arguments) = arguments.strip_prefix('<') {
+        arguments.split_once('>')?
+    } else {
+        arguments
+            .split_once(char::is_whitespace)
+            .unwrap_or((arguments, ""))
+    };
+    let user = user.trim();
+    if user.is_empty() {
+        None
+    } else {
+        Some((user.to_string(), rest.trim()))
+    }
+}
+
+/// Parse duration like `30s`, `10m`, `1h30m` or `7d`.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let mut seconds: u64 = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return None,
+        };
+        let value: u64 = number.parse().ok()?;
+        seconds = seconds.checked_add(value.checked_mul(unit)?)?;
+        number.clear();
+    }
+    if text.is_empty() || !number.is_empty() {
+        return None;
+    }
+    Some(Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::api::chat::{BanScope, PresenceStatus};
+
+    use super::{command_help, parse_duration, Command, CommandError};
+
+    #[test]
+    fn test_parse_messages() {
+        assert_eq!(
+            Command::parse(" Hello "),
+            Ok(Command::Message("Hello".to_string()))
+        );
+        assert_eq!(
+            Command::parse("//nick"),
+            Ok(Command::Message("/nick".to_string()))
+        );
+        assert_eq!(
+            Command::parse("/me waves"),
+            Ok(Command::Me("waves".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            Command::parse("/nick Alice"),
+            Ok(Command::Nick("Alice".to_string()))
+        );
+        assert_eq!(
+            Command::parse("/join #rust"),
+            Ok(Command::Join("rust".to_string()))
+        );
+        assert_eq!(Command::parse("/who"), Ok(Command::Who(None)));
+        assert_eq!(
+            Command::parse("/topic -"),
+            Ok(Command::Topic(Some(String::new())))
+        );
+        assert_eq!(
+            Command::parse("/status dnd"),
+            Ok(Command::Status(Some(PresenceStatus::DoNotDisturb)))
+        );
+        assert_eq!(
+            Command::parse("/seen <User 2>"),
+            Ok(Command::Seen("User 2".to_string()))
+        );
+        assert_eq!(
+            Command::parse("/msg <User 2> hi there"),
+            Ok(Command::Msg {
+                user: "User 2".to_string(),
+                message: "hi there".to_string()
+            })
+        );
+        assert_eq!(
+            Command::parse("/edit [42] Hello again"),
+            Ok(Command::Edit {
+                id: 42,
+                message: "Hello again".to_string()
+            })
+        );
+        assert_eq!(Command::parse("/delete 42"), Ok(Command::Delete(42)));
+        assert_eq!(
+            Command::parse("/reply 42 /fib 10 = 55"),
+            Ok(Command::Reply {
+                id: 42,
+                message: "/fib 10 = 55".to_string()
+            })
+        );
+        assert_eq!(Command::parse("/thread [42]"), Ok(Command::Thread(42)));
+        assert_eq!(
+            Command::parse("/react 42 👍"),
+            Ok(Command::React {
+                id: 42,
+                emoji: "👍".to_string()
+            })
+        );
+        assert_eq!(Command::parse("/fib 10"), Ok(Command::Fibonacci(10)));
+        assert_eq!(Command::parse("/fact 20"), Ok(Command::Factorial(20)));
+        assert_eq!(
+            Command::parse("/help fib"),
+            Ok(Command::Help(command_help("fib")))
+        );
+        assert_eq!(
+            Command::parse("/ban Mallory 1h spam"),
+            Ok(Command::Ban {
+                user: "Mallory".to_string(),
+                scope: BanScope::Account,
+                duration: Some(Duration::from_secs(3600)),
+                reason: "spam".to_string()
+            })
+        );
+        assert_eq!(
+            Command::parse("/banip <Mallory 2> spam"),
+            Ok(Command::Ban {
+                user: "Mallory 2".to_string(),
+                scope: BanScope::Address,
+                duration: None,
+                reason: "spam".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Command::parse("/dance"),
+            Err(CommandError::Unknown("dance".to_string()))
+        );
+        assert_eq!(
+            Command::parse("/nick"),
+            Err(CommandError::Usage(command_help("nick").unwrap()))
+        );
+        assert_eq!(
+            Command::parse("/join two rooms"),
+            Err(CommandError::InvalidArgument(
+                "room name must have between 1 and 32 characters \
+                and can contain only letters, digits, '_' and '-'"
+                    .to_string()
+            ))
+        );
+        assert!(matches!(
+            Command::parse("/fib -1"),
+            Err(CommandError::InvalidArgument(_))
+        ));
+        assert_eq!(
+            Command::parse("/edit 42"),
+            Err(CommandError::Usage(command_help("edit").unwrap()))
+        );
+        assert!(matches!(
+            Command::parse("/unreact 42 +1"),
+            Err(CommandError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            Command::parse("/delete last"),
+            Err(CommandError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            Command::parse("/mute Bob soon"),
+            Err(CommandError::InvalidArgument(_))
+        ));
+        assert_eq!(
+            Command::parse("/msg Alice"),
+            Err(CommandError::Usage(command_help("msg").unwrap()))
+        );
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("1h30m"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("7d"), Some(Duration::from_secs(604800)));
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod api;
+pub mod command;
 
 use std::mem::replace;
 
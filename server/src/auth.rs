@@ -0,0 +1,330 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Debug},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use common::api::{
+    auth::{AuthError, Credentials, MIN_PASSWORD_LENGTH},
+    chat::validate_user_name,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::error;
+
+/// Registered account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Account {
+    name: String,
+    /// Salted password hash in PHC string format.
+    password_hash: String,
+}
+
+/// Registered accounts, optionally persisted to a file (one JSON object per line).
+#[derive(Debug, Default)]
+pub struct Accounts {
+    /// Accounts by lowercase name.
+    accounts: HashMap<String, Account>,
+    /// File accounts are appended to, written unbuffered so that records
+    /// of accounts that failed to be stored are not written later.
+    file: Option<File>,
+    /// Lowercase names of accounts which can't be registered through API,
+    /// only added by server operator.
+    reserved: HashSet<String>,
+}
+
+impl Accounts {
+    /// Load accounts from file, new accounts will be appended to it.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("unable to open accounts file {path:?}"))?;
+
+        let mut accounts = HashMap::new();
+        for (number, line) in BufReader::new(&file).lines().enumerate() {
+            let line = line.with_context(|| format!("unable to read accounts file {path:?}"))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let account: Account = serde_json::from_str(&line).with_context(|| {
+                format!(
+                    "invalid account in accounts file {path:?} at line {}",
+                    number + 1
+                )
+            })?;
+            accounts.insert(account.name.to_lowercase(), account);
+        }
+
+        Ok(Accounts {
+            accounts,
+            file: Some(file),
+            reserved: HashSet::new(),
+        })
+    }
+
+    /// Check if account with given name (case insensitive) is registered.
+    pub fn exists(&self, name: &str) -> bool {
+        self.accounts.contains_key(&name.to_lowercase())
+    }
+
+    /// Reserve account names (case insensitive), so that they can't be registered through API.
+    ///
+    /// Used for accounts with roles, which are granted by account name.
+    pub fn reserve(&mut self, names: impl IntoIterator<Item = String>) {
+        self.reserved
+            .extend(names.into_iter().map(|name| name.to_lowercase()));
+    }
+
+    /// Register new account through API, fails if its name is reserved or it can't be stored.
+    pub fn register(&mut self, credentials: &Credentials) -> Result<(), AuthError> {
+        let key = credentials.name.to_lowercase();
+        if self.reserved.contains(&key) && !self.accounts.contains_key(&key) {
+            return Err(AuthError::ReservedName);
+        }
+        self.add(credentials)
+    }
+
+    /// Add new account even if its name is reserved, fails if it can't be stored.
+    pub fn add(&mut self, credentials: &Credentials) -> Result<(), AuthError> {
+        validate_user_name(&credentials.name).map_err(AuthError::InvalidName)?;
+        if credentials.password.chars().count() < MIN_PASSWORD_LENGTH {
+            return Err(AuthError::PasswordTooShort);
+        }
+        let key = credentials.name.to_lowercase();
+        if self.accounts.contains_key(&key) {
+            return Err(AuthError::AccountExists);
+        }
+
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = Argon2::default()
+            .hash_password(credentials.password.as_bytes(), &salt)
+            .expect("unable to hash password")
+            .to_string();
+        let account = Account {
+            name: credentials.name.clone(),
+            password_hash,
+        };
+        self.persist(&account).map_err(|error| {
+            error!("Unable to store account: {error:#}.");
+            AuthError::Internal
+        })?;
+        self.accounts.insert(key, account);
+        Ok(())
+    }
+
+    /// Check credentials and return name of account as it was registered.
+    pub fn login(&self, credentials: &Credentials) -> Result<String, AuthError> {
+        let account = self
+            .accounts
+            .get(&credentials.name.to_lowercase())
+            .ok_or(AuthError::InvalidCredentials)?;
+        let password_hash =
+            PasswordHash::new(&account.password_hash).map_err(|_| AuthError::InvalidCredentials)?;
+        Argon2::default()
+            .verify_password(credentials.password.as_bytes(), &password_hash)
+            .map_err(|_| AuthError::InvalidCredentials)?;
+        Ok(account.name.clone())
+    }
+
+    fn persist(&mut self, account: &Account) -> Result<()> {
+        if let Some(file) = &mut self.file {
+            let mut line = serde_json::to_vec(account)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Contents of bearer token.
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    account: String,
+    /// Unix timestamp (in seconds) after which token is no longer valid.
+    expires: i64,
+}
+
+/// Issues and verifies bearer tokens signed with HMAC-SHA256.
+///
+/// Token consists of base64 encoded JSON [Claims] and base64 encoded signature of them,
+/// separated by a dot.
+pub struct Tokens {
+    secret: Vec<u8>,
+    lifetime: Duration,
+}
+
+impl Tokens {
+    pub fn new(secret: Vec<u8>, lifetime: Duration) -> Self {
+        Tokens { secret, lifetime }
+    }
+
+    /// Issue token for account with given name.
+    pub fn issue(&self, account: &str) -> String {
+        let claims = Claims {
+            account: account.to_string(),
+            expires: Utc::now().timestamp() + self.lifetime.as_secs() as i64,
+        };
+        let claims = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap());
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&claims).finalize().into_bytes());
+        format!("{claims}.{signature}")
+    }
+
+    /// Verify token and return name of account it was issued for.
+    pub fn verify(&self, token: &str) -> Result<String, AuthError> {
+        let (claims, signature) = token.split_once('.').ok_or(AuthError::InvalidToken)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| AuthError::InvalidToken)?;
+        self.mac(claims)
+            .verify_slice(&signature)
+            .map_err(|_| AuthError::InvalidToken)?;
+        let claims = URL_SAFE_NO_PAD
+            .decode(claims)
+            .map_err(|_| AuthError::InvalidToken)?;
+        let claims: Claims =
+            serde_json::from_slice(&claims).map_err(|_| AuthError::InvalidToken)?;
+        if claims.expires < Utc::now().timestamp() {
+            return Err(AuthError::InvalidToken);
+        }
+        Ok(claims.account)
+    }
+
+    fn mac(&self, claims: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(claims.as_bytes());
+        mac
+    }
+}
+
+impl Debug for Tokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tokens")
+            .field("lifetime", &self.lifetime)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env::temp_dir,
+        fs::{self, File},
+        process,
+        time::Duration,
+    };
+
+    use common::api::auth::{AuthError, Credentials};
+
+    use super::{Accounts, Tokens};
+
+    fn credentials(name: &str, password: &str) -> Credentials {
+        Credentials {
+            name: name.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_accounts() {
+        let mut accounts = Accounts::default();
+        accounts
+            .register(&credentials("Alice", "correct horse"))
+            .unwrap();
+        assert_eq!(
+            accounts.register(&credentials("alice", "battery staple")),
+            Err(AuthError::AccountExists)
+        );
+        assert_eq!(
+            accounts.register(&credentials("Bob", "short")),
+            Err(AuthError::PasswordTooShort)
+        );
+
+        assert_eq!(
+            accounts.login(&credentials("ALICE", "correct horse")),
+            Ok("Alice".to_string())
+        );
+        assert_eq!(
+            accounts.login(&credentials("Alice", "wrong password")),
+            Err(AuthError::InvalidCredentials)
+        );
+        assert_eq!(
+            accounts.login(&credentials("Bob", "correct horse")),
+            Err(AuthError::InvalidCredentials)
+        );
+    }
+
+    #[test]
+    fn test_accounts_storage_failure() {
+        let path = temp_dir().join(format!("accounts-test-{}.jsonl", process::id()));
+        let _ = fs::remove_file(&path);
+        let mut accounts = Accounts::open(&path).unwrap();
+        accounts
+            .register(&credentials("Alice", "correct horse"))
+            .unwrap();
+
+        // file opened for reading only can't be written to
+        accounts.file = Some(File::open(&path).unwrap());
+        assert_eq!(
+            accounts.register(&credentials("Bob", "battery staple")),
+            Err(AuthError::Internal)
+        );
+        assert!(!accounts.exists("Bob"));
+
+        let accounts = Accounts::open(&path).unwrap();
+        assert!(accounts.exists("Alice"));
+        assert!(!accounts.exists("Bob"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reserved_accounts() {
+        let mut accounts = Accounts::default();
+        accounts.reserve(["Alice".to_string()]);
+        assert_eq!(
+            accounts.register(&credentials("ALICE", "correct horse")),
+            Err(AuthError::ReservedName)
+        );
+        assert!(!accounts.exists("Alice"));
+
+        accounts
+            .add(&credentials("Alice", "correct horse"))
+            .unwrap();
+        assert_eq!(
+            accounts.login(&credentials("alice", "correct horse")),
+            Ok("Alice".to_string())
+        );
+        assert_eq!(
+            accounts.register(&credentials("Alice", "battery staple")),
+            Err(AuthError::AccountExists)
+        );
+    }
+
+    #[test]
+    fn test_tokens() {
+        let tokens = Tokens::new(b"secret".to_vec(), Duration::from_secs(60));
+        let token = tokens.issue("Alice");
+        assert_eq!(tokens.verify(&token), Ok("Alice".to_string()));
+
+        let other_tokens = Tokens::new(b"other secret".to_vec(), Duration::from_secs(60));
+        assert_eq!(other_tokens.verify(&token), Err(AuthError::InvalidToken));
+        assert_eq!(
+            tokens.verify(&token.replace('.', "")),
+            Err(AuthError::InvalidToken)
+        );
+    }
+}
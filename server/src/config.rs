@@ -0,0 +1,523 @@
+use std::{
+    collections::HashMap,
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use common::api::chat::Role;
+use serde::Deserialize;
+use tracing::Level;
+
+use crate::limits::Rate;
+
+/// Web app server
+#[derive(Parser, Debug, Default)]
+#[command(author, version, about, long_about = None)]
+pub struct Args {
+    /// Path to TOML configuration file.
+    #[arg(short, long, env = "SERVER_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address to listen on [default: 127.0.0.1].
+    #[arg(short, long, env = "SERVER_ADDRESS")]
+    pub address: Option<String>,
+
+    /// Port to listen on [default: 8080].
+    #[arg(short, long, env = "SERVER_PORT")]
+    pub port: Option<u16>,
+
+    /// Directory with static files [default: www].
+    #[arg(short, long, env = "SERVER_STATIC_DIR")]
+    pub static_dir: Option<PathBuf>,
+
+    /// Capacity of event broadcast channels [default: 10].
+    #[arg(long, env = "SERVER_CHANNEL_CAPACITY")]
+    pub channel_capacity: Option<usize>,
+
+    /// Capacity of room message broadcast channels [default: 100].
+    #[arg(long, env = "SERVER_MESSAGE_CHANNEL_CAPACITY")]
+    pub message_channel_capacity: Option<usize>,
+
+    /// Log level: trace, debug, info, warn or error [default: info].
+    #[arg(short, long, env = "SERVER_LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    /// File message history is persisted to [default: history.jsonl].
+    #[arg(long, env = "SERVER_HISTORY_FILE")]
+    pub history_file: Option<PathBuf>,
+
+    /// Seconds disconnected user's session can be resumed for [default: 30].
+    #[arg(long, env = "SERVER_SESSION_GRACE_PERIOD")]
+    pub session_grace_period: Option<u64>,
+
+    /// Seconds of inactivity after which user is marked as idle [default: 300].
+    #[arg(long, env = "SERVER_IDLE_TIMEOUT")]
+    pub idle_timeout: Option<u64>,
+
+    /// File registered accounts are persisted to [default: accounts.jsonl].
+    #[arg(long, env = "SERVER_ACCOUNTS_FILE")]
+    pub accounts_file: Option<PathBuf>,
+
+    /// Secret authentication tokens are signed with
+    /// [default: random, tokens are invalidated on restart].
+    #[arg(long, env = "SERVER_TOKEN_SECRET", hide_env_values = true)]
+    pub token_secret: Option<String>,
+
+    /// Seconds authentication token is valid for [default: 86400].
+    #[arg(long, env = "SERVER_TOKEN_LIFETIME")]
+    pub token_lifetime: Option<u64>,
+
+    /// TLS certificate chain file (PEM), enables HTTPS when set together with `tls_key`.
+    #[arg(long, env = "SERVER_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// TLS private key file (PEM).
+    #[arg(long, env = "SERVER_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Generate self-signed certificate for local development
+    /// if `tls_cert` and `tls_key` files don't exist.
+    #[arg(long, env = "SERVER_TLS_SELF_SIGNED")]
+    pub tls_self_signed: bool,
+
+    /// Maximum length of message (in characters) [default: 2000].
+    #[arg(long, env = "SERVER_MAX_MESSAGE_LENGTH")]
+    pub max_message_length: Option<usize>,
+
+    /// Messages per second single user can send on average [default: 1].
+    #[arg(long, env = "SERVER_MESSAGE_RATE")]
+    pub message_rate: Option<f64>,
+
+    /// Messages single user can send in a burst [default: 10].
+    #[arg(long, env = "SERVER_MESSAGE_BURST")]
+    pub message_burst: Option<u32>,
+
+    /// Messages per second all users connected from single IP address can send on average
+    /// [default: 5].
+    #[arg(long, env = "SERVER_IP_MESSAGE_RATE")]
+    pub ip_message_rate: Option<f64>,
+
+    /// Messages all users connected from single IP address can send in a burst [default: 30].
+    #[arg(long, env = "SERVER_IP_MESSAGE_BURST")]
+    pub ip_message_burst: Option<u32>,
+
+    /// Maximum number of simultaneous connections from single IP address [default: 10].
+    #[arg(long, env = "SERVER_MAX_CONNECTIONS_PER_IP")]
+    pub max_connections_per_ip: Option<usize>,
+
+    /// Comma-separated names of accounts with admin role.
+    #[arg(long, env = "SERVER_ADMINS", value_delimiter = ',')]
+    pub admins: Option<Vec<String>>,
+
+    /// Comma-separated names of accounts with moderator role.
+    #[arg(long, env = "SERVER_MODERATORS", value_delimiter = ',')]
+    pub moderators: Option<Vec<String>>,
+
+    /// File bans are persisted to [default: bans.jsonl].
+    #[arg(long, env = "SERVER_BANS_FILE")]
+    pub bans_file: Option<PathBuf>,
+
+    /// Seconds users are warned about shutdown before connections are closed [default: 5].
+    #[arg(long, env = "SERVER_SHUTDOWN_WARNING")]
+    pub shutdown_warning: Option<u64>,
+
+    /// Seconds server waits for in-flight requests and connections to finish
+    /// during shutdown [default: 10].
+    #[arg(long, env = "SERVER_SHUTDOWN_TIMEOUT")]
+    pub shutdown_timeout: Option<u64>,
+
+    /// Add account with given name (reading its password from standard input) and exit.
+    /// Accounts listed in `admins` and `moderators` can't be registered through API
+    /// and have to be added this way.
+    #[arg(long, value_name = "NAME")]
+    pub add_account: Option<String>,
+}
+
+/// Contents of configuration file.
+///
+/// All settings are optional, command line arguments and environment variables
+/// take precedence over values read from file.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct File {
+    address: Option<String>,
+    port: Option<u16>,
+    static_dir: Option<PathBuf>,
+    channel_capacity: Option<usize>,
+    message_channel_capacity: Option<usize>,
+    log_level: Option<String>,
+    history_file: Option<PathBuf>,
+    session_grace_period: Option<u64>,
+    idle_timeout: Option<u64>,
+    accounts_file: Option<PathBuf>,
+    token_secret: Option<String>,
+    token_lifetime: Option<u64>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    tls_self_signed: Option<bool>,
+    max_message_length: Option<usize>,
+    message_rate: Option<f64>,
+    message_burst: Option<u32>,
+    ip_message_rate: Option<f64>,
+    ip_message_burst: Option<u32>,
+    max_connections_per_ip: Option<usize>,
+    admins: Option<Vec<String>>,
+    moderators: Option<Vec<String>>,
+    bans_file: Option<PathBuf>,
+    shutdown_warning: Option<u64>,
+    shutdown_timeout: Option<u64>,
+}
+
+impl File {
+    fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("unable to read configuration file {path:?}"))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("unable to parse configuration file {path:?}"))
+    }
+}
+
+/// Validated server configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub address: SocketAddr,
+    pub static_dir: PathBuf,
+    pub channel_capacity: usize,
+    pub message_channel_capacity: usize,
+    pub log_level: Level,
+    pub history_file: PathBuf,
+    pub session_grace_period: Duration,
+    pub idle_timeout: Duration,
+    pub accounts_file: PathBuf,
+    /// `None` if random secret should be generated.
+    pub token_secret: Option<String>,
+    pub token_lifetime: Duration,
+    /// `None` if server should serve plain HTTP.
+    pub tls: Option<Tls>,
+    pub max_message_length: usize,
+    pub user_message_rate: Rate,
+    pub ip_message_rate: Rate,
+    pub max_connections_per_ip: usize,
+    /// Roles of accounts (by lowercase account name) other than [Role::User].
+    pub roles: HashMap<String, Role>,
+    pub bans_file: PathBuf,
+    pub shutdown_warning: Duration,
+    pub shutdown_timeout: Duration,
+    /// `Some` if account should be added instead of running server.
+    pub add_account: Option<String>,
+}
+
+/// TLS settings.
+#[derive(Debug, Clone)]
+pub struct Tls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Whether self-signed certificate should be generated if files don't exist.
+    pub self_signed: bool,
+}
+
+impl Config {
+    /// Load configuration from command line arguments, environment variables
+    /// and configuration file (in that order of precedence).
+    pub fn load() -> Result<Self> {
+        Self::from_args(Args::parse())
+    }
+
+    pub fn from_args(args: Args) -> Result<Self> {
+        let file = match &args.config {
+            Some(path) => File::load(path)?,
+            None => File::default(),
+        };
+
+        let address = args
+            .address
+            .or(file.address)
+            .unwrap_or_else(|| "127.0.0.1".to_string());
+        let address: IpAddr = address
+            .parse()
+            .map_err(|error| anyhow!("invalid `address` setting \"{address}\": {error}"))?;
+        let port = args.port.or(file.port).unwrap_or(8080);
+
+        let static_dir = args
+            .static_dir
+            .or(file.static_dir)
+            .unwrap_or_else(|| PathBuf::from("www"));
+        if !static_dir.is_dir() {
+            bail!("invalid `static_dir` setting: {static_dir:?} is not a directory");
+        }
+
+        let channel_capacity = args
+            .channel_capacity
+            .or(file.channel_capacity)
+            .unwrap_or(10);
+        if channel_capacity == 0 {
+            bail!("invalid `channel_capacity` setting: must be greater than 0");
+        }
+
+        let message_channel_capacity = args
+            .message_channel_capacity
+            .or(file.message_channel_capacity)
+            .unwrap_or(100);
+        if message_channel_capacity == 0 {
+            bail!("invalid `message_channel_capacity` setting: must be greater than 0");
+        }
+
+        let log_level = args
+            .log_level
+            .or(file.log_level)
+            .unwrap_or_else(|| "info".to_string());
+        let log_level: Level = log_level.parse().map_err(|_| {
+            anyhow!(
+                "invalid `log_level` setting \"{log_level}\": \
+                expected one of trace, debug, info, warn, error"
+            )
+        })?;
+
+        let history_file = args
+            .history_file
+            .or(file.history_file)
+            .unwrap_or_else(|| PathBuf::from("history.jsonl"));
+        if history_file.is_dir() {
+            bail!("invalid `history_file` setting: {history_file:?} is a directory");
+        }
+
+        let session_grace_period = Duration::from_secs(
+            args.session_grace_period
+                .or(file.session_grace_period)
+                .unwrap_or(30),
+        );
+        let idle_timeout =
+            Duration::from_secs(args.idle_timeout.or(file.idle_timeout).unwrap_or(300));
+
+        let accounts_file = args
+            .accounts_file
+            .or(file.accounts_file)
+            .unwrap_or_else(|| PathBuf::from("accounts.jsonl"));
+        if accounts_file.is_dir() {
+            bail!("invalid `accounts_file` setting: {accounts_file:?} is a directory");
+        }
+
+        let token_secret = args.token_secret.or(file.token_secret);
+        if token_secret
+            .as_ref()
+            .is_some_and(|secret| secret.is_empty())
+        {
+            bail!("invalid `token_secret` setting: must not be empty");
+        }
+
+        let token_lifetime = args.token_lifetime.or(file.token_lifetime).unwrap_or(86400);
+        if token_lifetime == 0 {
+            bail!("invalid `token_lifetime` setting: must be greater than 0");
+        }
+
+        let tls_self_signed = args.tls_self_signed || file.tls_self_signed.unwrap_or(false);
+        let tls = match (
+            args.tls_cert.or(file.tls_cert),
+            args.tls_key.or(file.tls_key),
+        ) {
+            (Some(cert), Some(key)) => {
+                for (setting, path) in [("tls_cert", &cert), ("tls_key", &key)] {
+                    if path.is_dir() || (!tls_self_signed && !path.is_file()) {
+                        bail!("invalid `{setting}` setting: {path:?} is not a file");
+                    }
+                }
+                Some(Tls {
+                    cert,
+                    key,
+                    self_signed: tls_self_signed,
+                })
+            }
+            (None, None) if tls_self_signed => {
+                bail!(
+                    "invalid `tls_self_signed` setting: requires `tls_cert` and `tls_key` settings"
+                )
+            }
+            (None, None) => None,
+            _ => bail!("invalid TLS settings: `tls_cert` and `tls_key` have to be set together"),
+        };
+
+        let max_message_length = args
+            .max_message_length
+            .or(file.max_message_length)
+            .unwrap_or(2000);
+        if max_message_length == 0 {
+            bail!("invalid `max_message_length` setting: must be greater than 0");
+        }
+
+        let user_message_rate = rate(
+            ("message_rate", args.message_rate.or(file.message_rate), 1.0),
+            (
+                "message_burst",
+                args.message_burst.or(file.message_burst),
+                10,
+            ),
+        )?;
+        let ip_message_rate = rate(
+            (
+                "ip_message_rate",
+                args.ip_message_rate.or(file.ip_message_rate),
+                5.0,
+            ),
+            (
+                "ip_message_burst",
+                args.ip_message_burst.or(file.ip_message_burst),
+                30,
+            ),
+        )?;
+
+        let max_connections_per_ip = args
+            .max_connections_per_ip
+            .or(file.max_connections_per_ip)
+            .unwrap_or(10);
+        if max_connections_per_ip == 0 {
+            bail!("invalid `max_connections_per_ip` setting: must be greater than 0");
+        }
+
+        let mut roles = HashMap::new();
+        for (setting, accounts, role) in [
+            (
+                "moderators",
+                args.moderators.or(file.moderators),
+                Role::Moderator,
+            ),
+            ("admins", args.admins.or(file.admins), Role::Admin),
+        ] {
+            for account in accounts.unwrap_or_default() {
+                let account = account.trim();
+                if account.is_empty() {
+                    bail!("invalid `{setting}` setting: account name must not be empty");
+                }
+                roles.insert(account.to_lowercase(), role);
+            }
+        }
+
+        let bans_file = args
+            .bans_file
+            .or(file.bans_file)
+            .unwrap_or_else(|| PathBuf::from("bans.jsonl"));
+        if bans_file.is_dir() {
+            bail!("invalid `bans_file` setting: {bans_file:?} is a directory");
+        }
+
+        let shutdown_warning =
+            Duration::from_secs(args.shutdown_warning.or(file.shutdown_warning).unwrap_or(5));
+        let shutdown_timeout = Duration::from_secs(
+            args.shutdown_timeout
+                .or(file.shutdown_timeout)
+                .unwrap_or(10),
+        );
+
+        Ok(Config {
+            address: SocketAddr::new(address, port),
+            static_dir,
+            channel_capacity,
+            message_channel_capacity,
+            log_level,
+            history_file,
+            session_grace_period,
+            idle_timeout,
+            accounts_file,
+            token_secret,
+            token_lifetime: Duration::from_secs(token_lifetime),
+            tls,
+            max_message_length,
+            user_message_rate,
+            ip_message_rate,
+            max_connections_per_ip,
+            roles,
+            bans_file,
+            shutdown_warning,
+            shutdown_timeout,
+            add_account: args.add_account,
+        })
+    }
+}
+
+/// Validate rate limit settings given as (setting name, value, default value).
+fn rate(
+    (rate_setting, per_second, default_per_second): (&str, Option<f64>, f64),
+    (burst_setting, burst, default_burst): (&str, Option<u32>, u32),
+) -> Result<Rate> {
+    let per_second = per_second.unwrap_or(default_per_second);
+    if !(per_second.is_finite() && per_second > 0.0) {
+        bail!("invalid `{rate_setting}` setting: must be greater than 0");
+    }
+    let burst = burst.unwrap_or(default_burst);
+    if burst == 0 {
+        bail!("invalid `{burst_setting}` setting: must be greater than 0");
+    }
+    Ok(Rate { per_second, burst })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tracing::Level;
+
+    use super::{Args, Config};
+
+    fn args() -> Args {
+        Args {
+            static_dir: Some(PathBuf::from(".")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::from_args(args()).unwrap();
+        assert_eq!(config.address, ([127, 0, 0, 1], 8080).into());
+        assert_eq!(config.channel_capacity, 10);
+        assert_eq!(config.log_level, Level::INFO);
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let error = Config::from_args(Args {
+            address: Some("localhost:80".to_string()),
+            ..args()
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("`address`"));
+
+        let error = Config::from_args(Args {
+            channel_capacity: Some(0),
+            ..args()
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("`channel_capacity`"));
+
+        let error = Config::from_args(Args {
+            log_level: Some("loud".to_string()),
+            ..args()
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("`log_level`"));
+
+        let error = Config::from_args(Args {
+            tls_cert: Some(PathBuf::from("cert.pem")),
+            ..args()
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("`tls_key`"));
+
+        let error = Config::from_args(Args {
+            message_rate: Some(-1.0),
+            ..args()
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("`message_rate`"));
+
+        let error = Config::from_args(Args {
+            admins: Some(vec!["Alice".to_string(), " ".to_string()]),
+            ..args()
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("`admins`"));
+    }
+}
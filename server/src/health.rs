@@ -0,0 +1,162 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::time::timeout;
+use tracing::warn;
+
+use crate::State;
+
+/// How long readiness probe waits for state lock.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Server lifecycle information reported by health probes.
+#[derive(Debug)]
+pub struct Health {
+    started: Instant,
+    draining: AtomicBool,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            started: Instant::now(),
+            draining: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Health {
+    /// Mark server as shutting down, it won't be reported as ready anymore.
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Build report of live server.
+    pub fn report(&self, connected_users: i64) -> Report {
+        Report {
+            status: Status::Ok,
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_seconds: self.started.elapsed().as_secs(),
+            connected_users,
+            checks: None,
+        }
+    }
+
+    /// Check if server can serve new connections and build report.
+    pub async fn readiness(&self, state: &State, connected_users: i64) -> Report {
+        let (state_lock, storage) = match timeout(LOCK_TIMEOUT, state.read()).await {
+            Ok(state) => (
+                true,
+                state
+                    .storage
+                    .check()
+                    .inspect_err(|error| warn!("Storage is not ready: {error:#}."))
+                    .is_ok(),
+            ),
+            Err(_) => {
+                warn!("State lock couldn't be acquired in {LOCK_TIMEOUT:?}.");
+                (false, false)
+            }
+        };
+        let checks = Checks {
+            state_lock,
+            storage,
+            draining: self.is_draining(),
+        };
+        Report {
+            status: if checks.is_ready() {
+                Status::Ok
+            } else {
+                Status::Unavailable
+            },
+            checks: Some(checks),
+            ..self.report(connected_users)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Unavailable,
+}
+
+/// Results of readiness checks.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Checks {
+    /// Whether state lock could be acquired.
+    pub state_lock: bool,
+    /// Whether message storage is reachable.
+    pub storage: bool,
+    /// Whether server is shutting down.
+    pub draining: bool,
+}
+
+impl Checks {
+    pub fn is_ready(&self) -> bool {
+        self.state_lock && self.storage && !self.draining
+    }
+}
+
+/// Response of `/healthz` and `/readyz`.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub status: Status,
+    pub version: &'static str,
+    pub uptime_seconds: u64,
+    pub connected_users: i64,
+    /// Readiness checks (only reported by `/readyz`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checks: Option<Checks>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use tokio::sync::RwLock;
+
+    use super::{Health, Status};
+    use crate::{
+        moderation::Bans,
+        state::{Capacities, State},
+        storage::MemoryStorage,
+    };
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let state = Arc::new(RwLock::new(State::new(
+            Capacities {
+                events: 16,
+                messages: 16,
+            },
+            Box::<MemoryStorage>::default(),
+            Duration::from_secs(1),
+            HashMap::new(),
+            Bans::default(),
+        )));
+        let health = Health::default();
+        let report = health.readiness(&state, 3).await;
+        assert_eq!(report.status, Status::Ok);
+        assert_eq!(report.connected_users, 3);
+
+        let lock = state.write().await;
+        let report = health.readiness(&state, 3).await;
+        assert_eq!(report.status, Status::Unavailable);
+        assert!(!report.checks.unwrap().state_lock);
+        drop(lock);
+
+        health.start_draining();
+        let report = health.readiness(&state, 3).await;
+        assert_eq!(report.status, Status::Unavailable);
+        assert!(report.checks.unwrap().draining);
+    }
+}
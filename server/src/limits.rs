@@ -0,0 +1,259 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use common::api::chat::ChatError;
+
+/// Number of buckets after which full (unused) buckets are removed.
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Sustained rate and burst size of rate limiter.
+#[derive(Debug, Clone, Copy)]
+pub struct Rate {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_second).min(rate.burst as f64);
+        self.updated = now;
+    }
+}
+
+/// Token bucket rate limiter with separate bucket for each key.
+#[derive(Debug)]
+pub struct RateLimiter<K> {
+    rate: Rate,
+    buckets: Mutex<HashMap<K, TokenBucket>>,
+}
+
+impl<K> RateLimiter<K>
+where
+    K: Eq + Hash + Clone,
+{
+    pub fn new(rate: Rate) -> Self {
+        RateLimiter {
+            rate,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check if bucket of given key has a token, without taking it.
+    pub fn has_token(&self, key: &K) -> bool {
+        self.has_token_at(key, Instant::now())
+    }
+
+    /// Take token from bucket of given key, see [RateLimiter::has_token].
+    pub fn take(&self, key: &K) {
+        self.take_at(key, Instant::now());
+    }
+
+    fn has_token_at(&self, key: &K, now: Instant) -> bool {
+        self.with_bucket(key, now, |bucket| bucket.tokens >= 1.0)
+    }
+
+    fn take_at(&self, key: &K, now: Instant) {
+        self.with_bucket(key, now, |bucket| {
+            bucket.tokens = (bucket.tokens - 1.0).max(0.0);
+        });
+    }
+
+    /// Call `f` with refilled bucket of given key.
+    fn with_bucket<T>(&self, key: &K, now: Instant, f: impl FnOnce(&mut TokenBucket) -> T) -> T {
+        let rate = self.rate;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| {
+                bucket.refill(rate, now);
+                bucket.tokens < rate.burst as f64
+            });
+        }
+        let bucket = buckets.entry(key.clone()).or_insert(TokenBucket {
+            tokens: rate.burst as f64,
+            updated: now,
+        });
+        bucket.refill(rate, now);
+        f(bucket)
+    }
+}
+
+/// Limits number of simultaneous connections from single IP address.
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    max_connections: usize,
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_connections: usize) -> Self {
+        ConnectionLimiter {
+            max_connections,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Register new connection from given address,
+    /// return `None` if there are too many connections from it already.
+    ///
+    /// Connection is unregistered once returned guard is dropped.
+    pub fn acquire(&self, ip: IpAddr) -> Option<ConnectionGuard> {
+        let mut connections = self.connections.lock().unwrap();
+        let count = connections.entry(ip).or_default();
+        if *count >= self.max_connections {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionGuard {
+            ip,
+            connections: self.connections.clone(),
+        })
+    }
+}
+
+/// Registered connection, see [ConnectionLimiter::acquire].
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    ip: IpAddr,
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ConnectionGuard {
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Flood protection limits.
+#[derive(Debug)]
+pub struct Limits {
+    pub max_message_length: usize,
+    /// Message rate limiter keyed by account name.
+    pub user_messages: RateLimiter<String>,
+    /// Message rate limiter keyed by sender address.
+    pub ip_messages: RateLimiter<IpAddr>,
+    pub connections: ConnectionLimiter,
+}
+
+impl Limits {
+    /// Check if message can be sent by user with given account from given address.
+    ///
+    /// Rate limits are only charged once message is accepted, see [Limits::message_accepted].
+    pub fn check_message(&self, account: &str, ip: IpAddr, message: &str) -> Result<(), ChatError> {
+        if message.chars().count() > self.max_message_length {
+            return Err(ChatError::MessageTooLong {
+                max_length: self.max_message_length,
+            });
+        }
+        if !self.user_messages.has_token(&account.to_string()) || !self.ip_messages.has_token(&ip) {
+            return Err(ChatError::RateLimited);
+        }
+        Ok(())
+    }
+
+    /// Charge rate limits of user with given account and given address for accepted message.
+    pub fn message_accepted(&self, account: &str, ip: IpAddr) {
+        self.user_messages.take(&account.to_string());
+        self.ip_messages.take(&ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
+
+    use common::api::chat::ChatError;
+
+    use super::{ConnectionLimiter, Limits, Rate, RateLimiter};
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(Rate {
+            per_second: 2.0,
+            burst: 3,
+        });
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.has_token_at(&"Alice", now));
+            limiter.take_at(&"Alice", now);
+        }
+        assert!(!limiter.has_token_at(&"Alice", now));
+        assert!(limiter.has_token_at(&"Bob", now));
+
+        let later = now + Duration::from_millis(500);
+        assert!(limiter.has_token_at(&"Alice", later));
+        limiter.take_at(&"Alice", later);
+        assert!(!limiter.has_token_at(&"Alice", later));
+    }
+
+    #[test]
+    fn test_message_limits() {
+        let rate = Rate {
+            per_second: 0.001,
+            burst: 2,
+        };
+        let limits = Limits {
+            max_message_length: 5,
+            user_messages: RateLimiter::new(rate),
+            ip_messages: RateLimiter::new(rate),
+            connections: ConnectionLimiter::new(1),
+        };
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert_eq!(
+            limits.check_message("alice", ip, "Hello!"),
+            Err(ChatError::MessageTooLong { max_length: 5 })
+        );
+        // checking doesn't take tokens
+        for _ in 0..3 {
+            assert_eq!(limits.check_message("alice", ip, "Hi"), Ok(()));
+        }
+        limits.message_accepted("alice", ip);
+        limits.message_accepted("bob", ip);
+        assert_eq!(
+            limits.check_message("carol", ip, "Hi"),
+            Err(ChatError::RateLimited)
+        );
+        assert_eq!(
+            limits.check_message("alice", IpAddr::V4(Ipv4Addr::UNSPECIFIED), "Hi"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_connection_limiter() {
+        let limiter = ConnectionLimiter::new(2);
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let first = limiter.acquire(ip).unwrap();
+        let _second = limiter.acquire(ip).unwrap();
+        assert!(limiter.acquire(ip).is_none());
+
+        drop(first);
+        assert!(limiter.acquire(ip).is_some());
+    }
+}
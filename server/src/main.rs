@@ -1,52 +1,280 @@
+mod auth;
+mod config;
+mod health;
+mod limits;
+mod metrics;
+mod moderation;
 mod state;
+mod storage;
+mod tls;
 
-use std::{env::current_dir, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    env::current_dir,
+    io::stdin,
+    marker::PhantomData,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-use anyhow::Result;
-use futures::Stream;
+use anyhow::{Context, Result};
+use futures::{FutureExt, Stream};
 use kodec::binary::Codec;
 use mezzenger_websocket::warp::Transport;
-use tokio::{signal::ctrl_c, spawn, sync::RwLock};
-use tokio_stream::{wrappers::BroadcastStream, StreamExt};
-use tracing::{error, info, Level};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::{
+    select,
+    signal::ctrl_c,
+    spawn,
+    sync::{
+        broadcast::{self, error::RecvError, Receiver},
+        Mutex, RwLock,
+    },
+    task::spawn_blocking,
+    time::sleep,
+};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    StreamExt,
+};
+use tracing::{error, info, warn};
 use warp::{
     hyper::StatusCode,
+    reject::Reject,
     ws::{WebSocket, Ws},
-    Filter,
+    Filter, Rejection, Reply,
 };
 
+use auth::{Accounts, Tokens};
+use common::api::auth::{AuthError, Credentials, Token};
+use config::Config;
+use health::Health;
+use limits::{ConnectionGuard, ConnectionLimiter, Limits, RateLimiter};
+use metrics::{Metrics, WebSocketRejection};
+use moderation::Bans;
+use state::{Capacities, User};
+use storage::LogStorage;
+
+/// How often expired typing indicators are hidden.
+const TYPING_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long transport is kept open after user is kicked,
+/// so that event with reason is delivered first.
+const DISCONNECT_DELAY: Duration = Duration::from_millis(200);
+
+/// How often shutdown checks if calls and connections are finished.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 type State = Arc<RwLock<state::State>>;
+type SharedAccounts = Arc<Mutex<Accounts>>;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt().with_max_level(Level::INFO).init();
+    let config = Config::load()?;
+    tracing_subscriber::fmt()
+        .with_max_level(config.log_level)
+        .init();
+
+    if let Some(name) = &config.add_account {
+        return add_account(&config, name);
+    }
 
     info!("Server running!");
 
     let current_dir = current_dir()?;
     info!("Current working directory: {:?}.", current_dir);
+    info!("Serving static files from: {:?}.", config.static_dir);
 
-    let state = Arc::new(RwLock::new(state::State::new()));
-    let state = warp::any().map(move || state.clone());
-    let websocket = warp::path("ws")
-        .and(warp::ws())
-        .and(state)
-        .map(|ws: Ws, state| ws.on_upgrade(move |web_socket| user_connected(web_socket, state)));
+    info!("Loading message history from: {:?}.", config.history_file);
+    let storage = LogStorage::open(&config.history_file)?;
+    let capacities = Capacities {
+        events: config.channel_capacity,
+        messages: config.message_channel_capacity,
+    };
+    info!("Loading bans from: {:?}.", config.bans_file);
+    let bans = Bans::open(&config.bans_file)?;
+    let shared_state = Arc::new(RwLock::new(state::State::new(
+        capacities,
+        Box::new(storage),
+        config.session_grace_period,
+        config.roles.clone(),
+        bans,
+    )));
+    let state = {
+        let state = shared_state.clone();
+        warp::any().map(move || state.clone())
+    };
+    spawn(detect_idle_users(shared_state.clone(), config.idle_timeout));
+    spawn(expire_typing(shared_state.clone()));
 
-    let static_files = warp::get().and(warp::fs::dir("www"));
-    let routes = websocket.or(static_files).recover(handle_rejection);
+    info!("Loading accounts from: {:?}.", config.accounts_file);
+    let mut accounts = Accounts::open(&config.accounts_file)?;
+    accounts.reserve(config.roles.keys().cloned());
+    let accounts = Arc::new(Mutex::new(accounts));
+    let secret = match &config.token_secret {
+        Some(secret) => secret.as_bytes().to_vec(),
+        None => {
+            warn!("Token secret not configured, tokens will be invalidated on restart.");
+            rand::random::<[u8; 32]>().to_vec()
+        }
+    };
+    let tokens = Arc::new(Tokens::new(secret, config.token_lifetime));
+    let accounts = {
+        let accounts = accounts.clone();
+        warp::any().map(move || accounts.clone())
+    };
+    let tokens_filter = {
+        let tokens = tokens.clone();
+        warp::any().map(move || tokens.clone())
+    };
 
-    let (address, server_future) =
-        warp::serve(routes).bind_with_graceful_shutdown(([127, 0, 0, 1], 8080), async move {
-            ctrl_c()
-                .await
-                .expect("unable to listen for shutdown signal");
-        });
+    let register = warp::path!("auth" / "register")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(remote_ip())
+        .and(accounts.clone())
+        .and(tokens_filter.clone())
+        .and(state.clone())
+        .then(register);
+    let login = warp::path!("auth" / "login")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(remote_ip())
+        .and(accounts.clone())
+        .and(tokens_filter)
+        .and(state.clone())
+        .then(login);
+
+    let limits = Arc::new(Limits {
+        max_message_length: config.max_message_length,
+        user_messages: RateLimiter::new(config.user_message_rate),
+        ip_messages: RateLimiter::new(config.ip_message_rate),
+        connections: ConnectionLimiter::new(config.max_connections_per_ip),
+    });
+    let metrics = Arc::new(Metrics::default());
+    let metrics_route = {
+        let metrics = metrics.clone();
+        warp::path!("metrics").and(warp::get()).map(move || {
+            warp::reply::with_header(
+                metrics.render(),
+                "content-type",
+                "text/plain; version=0.0.4",
+            )
+        })
+    };
+    let health = Arc::new(Health::default());
+    let healthz = {
+        let health = health.clone();
+        let metrics = metrics.clone();
+        warp::path!("healthz")
+            .and(warp::get())
+            .map(move || warp::reply::json(&health.report(metrics.connected_users())))
+    };
+    let readyz = {
+        let health = health.clone();
+        let metrics = metrics.clone();
+        warp::path!("readyz")
+            .and(warp::get())
+            .and(state.clone())
+            .then(move |state: State| {
+                let health = health.clone();
+                let metrics = metrics.clone();
+                async move {
+                    let report = health.readiness(&state, metrics.connected_users()).await;
+                    let status = match report.status {
+                        health::Status::Ok => StatusCode::OK,
+                        health::Status::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+                    };
+                    warp::reply::with_status(warp::reply::json(&report), status)
+                }
+            })
+    };
+
+    let websocket = {
+        let metrics = metrics.clone();
+        let rejections = metrics.clone();
+        warp::path("ws").and(
+            accepting_connections(health.clone())
+                .and(authenticated(tokens))
+                .and(connection_slot(limits.clone()))
+                .and(state.clone())
+                .and_then(not_banned)
+                .untuple_one()
+                .and(warp::ws())
+                .and(state)
+                .and(accounts)
+                .map(
+                    move |account: String, connection, ws: Ws, state, accounts| {
+                        let limits = limits.clone();
+                        let metrics = metrics.clone();
+                        ws.on_upgrade(move |web_socket| {
+                            user_connected(
+                                web_socket, account, connection, state, accounts, limits, metrics,
+                            )
+                        })
+                        .into_response()
+                    },
+                )
+                .or_else(move |rejection: Rejection| {
+                    rejections.websocket_rejected(websocket_rejection(&rejection));
+                    async move { Err::<(warp::reply::Response,), _>(rejection) }
+                }),
+        )
+    };
+
+    let static_files = warp::get().and(warp::fs::dir(config.static_dir.clone()));
+    let routes = register
+        .or(login)
+        .or(websocket)
+        .or(metrics_route)
+        .or(healthz)
+        .or(readyz)
+        .or(static_files)
+        .recover(handle_rejection);
+
+    let shutdown = shutdown(
+        shared_state.clone(),
+        health,
+        metrics,
+        config.shutdown_warning,
+        config.shutdown_timeout,
+    );
+    let (address, server_future) = match &config.tls {
+        Some(tls) => {
+            tls::ensure_certificate(tls)?;
+            let (address, server_future) = warp::serve(routes)
+                .tls()
+                .cert_path(&tls.cert)
+                .key_path(&tls.key)
+                .try_bind_with_graceful_shutdown(config.address, shutdown)
+                .context("unable to start TLS server")?;
+            (address, server_future.boxed())
+        }
+        None => {
+            let (address, server_future) =
+                warp::serve(routes).bind_with_graceful_shutdown(config.address, shutdown);
+            (address, server_future.boxed())
+        }
+    };
     let server_handle = spawn(server_future);
-    info!("Listening at {}...", address);
+    let scheme = if config.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    info!("Listening at {scheme}://{address}...");
 
     server_handle.await?;
-    info!("Shutting down...");
+    if let Err(error) = shared_state.write().await.storage.flush() {
+        error!("Unable to flush message history: {error:#}.");
+    }
+    info!("Server stopped.");
 
     Ok(())
 }
@@ -54,107 +282,1065 @@ async fn main() -> Result<()> {
 use common::api::chat::*;
 use zzrpc::{
     producer::{Configuration, Produce},
-    Produce,
+    DefaultReceiveErrorCallback, DefaultSendErrorCallback, Produce, ShutdownType,
 };
 
 #[derive(Produce)]
 struct Producer {
     state: State,
-    user_name: String,
+    accounts: SharedAccounts,
+    limits: Arc<Limits>,
+    metrics: Arc<Metrics>,
+    /// Address user is connected from.
+    address: IpAddr,
+    /// Id of user served by this producer (changes when session is resumed).
+    user_id: Arc<AtomicUsize>,
 }
 
 impl Producer {
+    fn id(&self) -> usize {
+        self.user_id.load(Ordering::Relaxed)
+    }
+
+    /// Check if user can send given message, limits are charged by [Producer::charge_limits].
+    fn check_limits(&self, user: &User, message: &str) -> Result<(), ChatError> {
+        self.limits
+            .check_message(&user.account, self.address, message)
+            .inspect_err(|error| {
+                warn!(
+                    "Rejected message of user <{}> from {}: {error}.",
+                    user.name, self.address
+                );
+            })
+    }
+
+    /// Charge rate limits of user for accepted message.
+    fn charge_limits(&self, user: &User) {
+        self.limits.message_accepted(&user.account, self.address);
+    }
+
+    /// Stream of server events without connecting and disconnecting of user itself,
+    /// including events sent only to the user.
+    async fn user_events(&self) -> impl Stream<Item = ServerEvent> {
+        let (my_name, own_events) = match self.state.read().await.user(self.id()) {
+            Ok(user) => (Some(user.name.clone()), user.events_sender.subscribe()),
+            Err(_) => (None, closed_receiver()),
+        };
+        let events =
+            server_events(&self.state, &self.metrics)
+                .await
+                .filter(move |event| match event {
+                    ServerEvent::UserJoined { user } | ServerEvent::UserLeft { user } => {
+                        Some(user) != my_name.as_ref()
+                    }
+                    _ => true,
+                });
+        futures::stream::select(
+            events,
+            broadcast_stream(own_events, self.metrics.clone(), |missed| {
+                Some(ServerEvent::Gap { missed })
+            }),
+        )
+    }
+
     /// Get user name.
-    async fn user_name(&self) -> String {
-        self.user_name.clone()
+    async fn user_name(&self) -> Result<String, ChatError> {
+        let _timer = self.metrics.rpc("user_name");
+        let mut state = self.state.write().await;
+        state.announce_user(self.id());
+        Ok(state.user(self.id())?.name.clone())
+    }
+
+    /// Get user id.
+    async fn user_id(&self) -> usize {
+        let _timer = self.metrics.rpc("user_id");
+        self.id()
+    }
+
+    /// Get session information.
+    async fn session(&self) -> Result<Session, ChatError> {
+        let _timer = self.metrics.rpc("session");
+        let mut state = self.state.write().await;
+        state.announce_user(self.id());
+        let user = state.user(self.id())?;
+        Ok(Session {
+            user_id: user.id,
+            user_name: user.name.clone(),
+            token: user.token.clone(),
+        })
+    }
+
+    /// Resume session after reconnecting.
+    async fn resume(
+        &self,
+        token: String,
+        last_seen_message_id: u64,
+    ) -> Result<Vec<(String, MessageEvent)>, ChatError> {
+        let _timer = self.metrics.rpc("resume");
+        let mut state = self.state.write().await;
+        let id = state.resume_session(self.id(), &token)?;
+        self.user_id.store(id, Ordering::Relaxed);
+        info!("User <{}> resumed session.", state.users[&id].name);
+        Ok(state.missed_messages(id, last_seen_message_id))
     }
 
     /// Get other connected user names.
     async fn user_names(&self) -> Vec<String> {
-        let my_name = self.user_name.clone();
+        let _timer = self.metrics.rpc("user_names");
         self.state
             .read()
             .await
             .users
             .values()
-            .map(|user| &user.name)
-            .filter(move |name| name != &&my_name)
-            .cloned()
+            .filter(|user| user.connected && user.id != self.id())
+            .map(|user| user.name.clone())
             .collect()
     }
 
-    /// Send chat message.
-    async fn message(&self, message: String) {
-        let _ = self
+    /// Change user name.
+    async fn set_user_name(&self, name: String) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("set_user_name");
+        // names of other registered accounts can't be used to avoid impersonation
+        let account = self.state.read().await.user(self.id())?.account.clone();
+        if name.to_lowercase() != account.to_lowercase() && self.accounts.lock().await.exists(&name)
+        {
+            return Err(ChatError::NameTaken);
+        }
+        let old_name = self
             .state
+            .write()
+            .await
+            .rename_user(self.id(), name.clone())?;
+        if old_name != name {
+            info!("User <{old_name}> renamed to <{name}>.");
+        }
+        Ok(())
+    }
+
+    /// Get names of existing rooms.
+    async fn rooms(&self) -> Vec<String> {
+        let _timer = self.metrics.rpc("rooms");
+        self.state.read().await.rooms.keys().cloned().collect()
+    }
+
+    /// Create new room and join it.
+    async fn create_room(&self, room: String) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("create_room");
+        let mut state = self.state.write().await;
+        state.create_room(self.id(), &room)?;
+        info!(
+            "User <{}> created room #{room}.",
+            state.user(self.id())?.name
+        );
+        Ok(())
+    }
+
+    /// Join existing room.
+    async fn join_room(&self, room: String) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("join_room");
+        self.state.write().await.join_room(self.id(), &room)
+    }
+
+    /// Leave room.
+    async fn leave_room(&self, room: String) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("leave_room");
+        self.state.write().await.leave_room(self.id(), &room)
+    }
+
+    /// Get topic of room.
+    async fn topic(&self, room: String) -> Result<String, ChatError> {
+        let _timer = self.metrics.rpc("topic");
+        Ok(self.state.read().await.room(&room)?.topic.clone())
+    }
+
+    /// Set topic of room.
+    async fn set_topic(&self, room: String, topic: String) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("set_topic");
+        self.state.write().await.set_topic(self.id(), &room, &topic)
+    }
+
+    /// Get names of users in room.
+    async fn room_members(&self, room: String) -> Result<Vec<String>, ChatError> {
+        let _timer = self.metrics.rpc("room_members");
+        self.state.read().await.room_members(&room)
+    }
+
+    /// Send chat message to room.
+    async fn message(
+        &self,
+        room: String,
+        message: String,
+        reply_to: Option<u64>,
+    ) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("message");
+        let mut state = self.state.write().await;
+        self.check_limits(state.user(self.id())?, &message)?;
+        state.message(self.id(), &room, message, reply_to)?;
+        self.charge_limits(state.user(self.id())?);
+        self.metrics.message_sent();
+        Ok(())
+    }
+
+    /// Change body of message with given id.
+    async fn edit_message(&self, message_id: u64, message: String) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("edit_message");
+        let mut state = self.state.write().await;
+        self.check_limits(state.user(self.id())?, &message)?;
+        state.edit_message(self.id(), message_id, message)?;
+        self.charge_limits(state.user(self.id())?);
+        Ok(())
+    }
+
+    /// Delete message with given id.
+    async fn delete_message(&self, message_id: u64) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("delete_message");
+        self.state
+            .write()
+            .await
+            .delete_message(self.id(), message_id)
+    }
+
+    /// Get edits and deletions of message with given id.
+    async fn revisions(&self, message_id: u64) -> Result<Vec<Revision>, ChatError> {
+        let _timer = self.metrics.rpc("revisions");
+        self.state.read().await.revisions(self.id(), message_id)
+    }
+
+    /// React to message with given id.
+    async fn react(&self, message_id: u64, emoji: String) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("react");
+        let mut state = self.state.write().await;
+        self.check_limits(state.user(self.id())?, &emoji)?;
+        state.react(self.id(), message_id, emoji)?;
+        self.charge_limits(state.user(self.id())?);
+        Ok(())
+    }
+
+    /// Remove reaction to message with given id.
+    async fn unreact(&self, message_id: u64, emoji: String) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("unreact");
+        self.state
+            .write()
+            .await
+            .unreact(self.id(), message_id, &emoji)
+    }
+
+    /// Get latest messages sent to room before message with given id.
+    async fn history(
+        &self,
+        room: String,
+        before: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<ChatMessage>, ChatError> {
+        let _timer = self.metrics.rpc("history");
+        self.state
             .read()
             .await
-            .message_sender
-            .send((self.user_name.clone(), message));
+            .history(self.id(), &room, before, limit.min(MAX_HISTORY_LIMIT))
+    }
+
+    /// Get thread containing message with given id.
+    async fn thread(&self, message_id: u64) -> Result<Vec<ChatMessage>, ChatError> {
+        let _timer = self.metrics.rpc("thread");
+        self.state.read().await.thread(self.id(), message_id)
     }
 
-    /// Stream of pairs containing: (user name, message)
-    async fn messages(&self) -> impl Stream<Item = (String, String)> {
-        BroadcastStream::new(self.state.read().await.message_sender.subscribe())
-            .filter_map(Result::ok)
+    /// Stream of messages sent to room.
+    async fn messages(&self, room: String, backfill: bool) -> impl Stream<Item = MessageEvent> {
+        let _timer = self.metrics.rpc("messages");
+        let (messages, updates, left, last_id) = {
+            let state = self.state.read().await;
+            let last_id = state.storage.last_id();
+            state
+                .member_room(self.id(), &room)
+                .map(|room| {
+                    (
+                        room.message_sender.subscribe(),
+                        room.update_sender.subscribe(),
+                        room.left_sender.subscribe(),
+                        last_id,
+                    )
+                })
+                .unwrap_or_else(|_| {
+                    (
+                        closed_receiver(),
+                        closed_receiver(),
+                        closed_receiver(),
+                        last_id,
+                    )
+                })
+        };
+        let messages = room_messages(
+            self.state.clone(),
+            self.metrics.clone(),
+            room,
+            messages,
+            last_id,
+            backfill,
+        );
+        let updates = broadcast_stream(updates, self.metrics.clone(), |missed| {
+            Some(MessageEvent::UpdatesGap { missed })
+        });
+        futures::StreamExt::take_until(
+            futures::stream::select(messages, updates),
+            Box::pin(user_left(left, self.id())),
+        )
+    }
+
+    /// Send private message to user with given name.
+    async fn direct_message(&self, to: String, message: String) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("direct_message");
+        let mut state = self.state.write().await;
+        self.check_limits(state.user(self.id())?, &message)?;
+        state.direct_message(self.id(), &to, message)?;
+        self.charge_limits(state.user(self.id())?);
+        self.metrics.message_sent();
+        Ok(())
+    }
+
+    /// Stream of pairs containing: (sender name, message) sent privately to user.
+    async fn inbox(&self) -> impl Stream<Item = (String, String)> {
+        let _timer = self.metrics.rpc("inbox");
+        let metrics = self.metrics.clone();
+        BroadcastStream::new(
+            self.state
+                .read()
+                .await
+                .user(self.id())
+                .map(|user| user.inbox_sender.subscribe())
+                .unwrap_or_else(|_| closed_receiver()),
+        )
+        .filter_map(move |result| match result {
+            Ok(message) => {
+                metrics.message_delivered();
+                Some(message)
+            }
+            Err(BroadcastStreamRecvError::Lagged(count)) => {
+                metrics.messages_lagged(count);
+                None
+            }
+        })
+    }
+
+    /// Stream of names of users joining room.
+    async fn joined(&self, room: String) -> impl Stream<Item = String> {
+        let _timer = self.metrics.rpc("joined");
+        let receiver = self
+            .state
+            .read()
+            .await
+            .room(&room)
+            .map(|room| room.joined_sender.subscribe())
+            .unwrap_or_else(|_| closed_receiver());
+        broadcast_stream(receiver, self.metrics.clone(), |_| None).map(|(_, name)| name)
+    }
+
+    /// Stream of names of users leaving room.
+    async fn left(&self, room: String) -> impl Stream<Item = String> {
+        let _timer = self.metrics.rpc("left");
+        let receiver = self
+            .state
+            .read()
+            .await
+            .room(&room)
+            .map(|room| room.left_sender.subscribe())
+            .unwrap_or_else(|_| closed_receiver());
+        broadcast_stream(receiver, self.metrics.clone(), |_| None).map(|(_, name)| name)
+    }
+
+    /// Stream of events originating from server.
+    async fn events(&self) -> impl Stream<Item = ServerEvent> {
+        let _timer = self.metrics.rpc("events");
+        self.user_events().await
+    }
+
+    /// Send notice to all connected users.
+    async fn notice(&self, message: String) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("notice");
+        let state = self.state.read().await;
+        state.notice(self.id(), &message)?;
+        info!(
+            "User <{}> sent notice: {message}",
+            state.user(self.id())?.name
+        );
+        Ok(())
     }
 
     /// Stream of names of newly connected users.
     async fn connected(&self) -> impl Stream<Item = String> {
-        let my_name = self.user_name.clone();
-        BroadcastStream::new(self.state.read().await.connected_sender.subscribe())
-            .filter_map(Result::ok)
-            .filter(move |name| name != &my_name)
+        let _timer = self.metrics.rpc("connected");
+        self.user_events().await.filter_map(|event| match event {
+            ServerEvent::UserJoined { user } => Some(user),
+            _ => None,
+        })
     }
 
     /// Stream of names of disconnected users.
     async fn disconnected(&self) -> impl Stream<Item = String> {
-        let my_name = self.user_name.clone();
-        BroadcastStream::new(self.state.read().await.disconnected_sender.subscribe())
-            .filter_map(Result::ok)
-            .filter(move |name| name != &my_name)
+        let _timer = self.metrics.rpc("disconnected");
+        self.user_events().await.filter_map(|event| match event {
+            ServerEvent::UserLeft { user } => Some(user),
+            _ => None,
+        })
+    }
+
+    /// Stream of pairs containing: (old user name, new user name)
+    async fn renamed(&self) -> impl Stream<Item = (String, String)> {
+        let _timer = self.metrics.rpc("renamed");
+        server_events(&self.state, &self.metrics)
+            .await
+            .filter_map(|event| match event {
+                ServerEvent::UserRenamed { old_name, new_name } => Some((old_name, new_name)),
+                _ => None,
+            })
+    }
+
+    /// Report that client is still running.
+    async fn heartbeat(&self, active: bool) {
+        let _timer = self.metrics.rpc("heartbeat");
+        if active {
+            self.state.write().await.user_active(self.id());
+        }
+    }
+
+    /// Set status of user.
+    async fn set_status(&self, status: PresenceStatus) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("set_status");
+        self.state.write().await.set_status(self.id(), status)
+    }
+
+    /// Get presence of user with given name.
+    async fn user_presence(&self, user: String) -> Result<Presence, ChatError> {
+        let _timer = self.metrics.rpc("user_presence");
+        self.state.read().await.user_presence(&user)
+    }
+
+    /// Get presence of all users, including disconnected ones.
+    async fn presences(&self) -> Vec<Presence> {
+        let _timer = self.metrics.rpc("presences");
+        self.state.read().await.presences()
+    }
+
+    /// Stream of changes of presence of connected users.
+    async fn presence(&self) -> impl Stream<Item = Presence> {
+        let _timer = self.metrics.rpc("presence");
+        let receiver = self.state.read().await.presence_sender.subscribe();
+        let state = self.state.clone();
+        let metrics = self.metrics.clone();
+        let presence = futures::StreamExt::then(BroadcastStream::new(receiver), move |result| {
+            let state = state.clone();
+            let metrics = metrics.clone();
+            async move {
+                match result {
+                    Ok(presence) => vec![presence],
+                    Err(BroadcastStreamRecvError::Lagged(count)) => {
+                        metrics.messages_lagged(count);
+                        state.read().await.presences()
+                    }
+                }
+            }
+        });
+        Box::pin(futures::StreamExt::flat_map(
+            presence,
+            futures::stream::iter,
+        ))
+    }
+
+    /// Report that user is typing message to room.
+    async fn typing_started(&self, room: String) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("typing_started");
+        self.state.write().await.start_typing(self.id(), &room)
+    }
+
+    /// Report that user stopped typing message to room.
+    async fn typing_stopped(&self, room: String) {
+        let _timer = self.metrics.rpc("typing_stopped");
+        self.state.write().await.stop_typing(self.id(), &room);
+    }
+
+    /// Stream of changes of typing indicators in rooms user is member of.
+    async fn typing(&self) -> impl Stream<Item = Typing> {
+        let _timer = self.metrics.rpc("typing");
+        let receiver = self.state.read().await.typing_sender.subscribe();
+        let state = self.state.clone();
+        let metrics = self.metrics.clone();
+        let user_id = self.user_id.clone();
+        let typing = futures::StreamExt::then(BroadcastStream::new(receiver), move |result| {
+            let state = state.clone();
+            let metrics = metrics.clone();
+            let id = user_id.load(Ordering::Relaxed);
+            async move {
+                let state = state.read().await;
+                match result {
+                    Ok((typing_id, typing)) => {
+                        if typing_id != id && state.member_room(id, &typing.room).is_ok() {
+                            vec![typing]
+                        } else {
+                            vec![]
+                        }
+                    }
+                    Err(BroadcastStreamRecvError::Lagged(count)) => {
+                        metrics.messages_lagged(count);
+                        state.typing_snapshot(id)
+                    }
+                }
+            }
+        });
+        Box::pin(futures::StreamExt::flat_map(typing, futures::stream::iter))
+    }
+
+    /// Get role of user.
+    async fn role(&self) -> Result<Role, ChatError> {
+        let _timer = self.metrics.rpc("role");
+        Ok(self.state.read().await.user(self.id())?.role)
+    }
+
+    /// Disconnect user with given name.
+    async fn kick(&self, user: String, reason: String) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("kick");
+        let mut state = self.state.write().await;
+        let kicked = state.kick(self.id(), &user, &reason)?;
+        info!(
+            "User <{kicked}> was kicked by <{}>.",
+            state.user(self.id())?.name
+        );
+        Ok(())
+    }
+
+    /// Ban account or address of user with given name.
+    async fn ban(
+        &self,
+        user: String,
+        scope: BanScope,
+        duration: Option<Duration>,
+        reason: String,
+    ) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("ban");
+        let mut state = self.state.write().await;
+        let banned = state.ban(self.id(), &user, scope, duration, &reason)?;
+        info!(
+            "User <{user}> was banned ({scope:?}) by <{}> {}, disconnected users: {}.",
+            state.user(self.id())?.name,
+            moderation::format_duration(duration),
+            format_user_names(&banned)
+        );
+        Ok(())
+    }
+
+    /// Lift ban of account with given name or given IP address.
+    async fn unban(&self, target: String) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("unban");
+        let mut state = self.state.write().await;
+        if state.unban(self.id(), &target)? {
+            info!(
+                "Ban of {target} was lifted by <{}>.",
+                state.user(self.id())?.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Prevent user with given name from sending messages.
+    async fn mute(&self, user: String, duration: Option<Duration>) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("mute");
+        let mut state = self.state.write().await;
+        state.mute(self.id(), &user, duration)?;
+        info!(
+            "User <{user}> was muted by <{}> {}.",
+            state.user(self.id())?.name,
+            moderation::format_duration(duration)
+        );
+        Ok(())
+    }
+
+    /// Allow muted user with given name to send messages again.
+    async fn unmute(&self, user: String) -> Result<(), ChatError> {
+        let _timer = self.metrics.rpc("unmute");
+        let mut state = self.state.write().await;
+        state.unmute(self.id(), &user)?;
+        info!(
+            "User <{user}> was unmuted by <{}>.",
+            state.user(self.id())?.name
+        );
+        Ok(())
     }
 }
 
-async fn user_connected(web_socket: WebSocket, state: State) {
+async fn user_connected(
+    web_socket: WebSocket,
+    account: String,
+    connection: ConnectionGuard,
+    state: State,
+    accounts: SharedAccounts,
+    limits: Arc<Limits>,
+    metrics: Arc<Metrics>,
+) {
     let codec = Codec::default();
     let transport = Transport::new(web_socket, codec);
-    let (id, name) = {
+    let (id, disconnect) = {
         let mut state_lock = state.write().await;
-        let user = state_lock.add_user();
-        (user.id, user.name.clone())
+        let user = state_lock.add_user(&account, connection.ip());
+        info!(
+            "User <{}> connected from {} (account <{account}>, role {}).",
+            user.name,
+            connection.ip(),
+            user.role
+        );
+        (user.id, user.disconnect.clone())
     };
-    info!("User <{name}> connected.");
+    let user_id = Arc::new(AtomicUsize::new(id));
     let producer = Producer {
         state: state.clone(),
-        user_name: name.clone(),
+        accounts,
+        limits,
+        metrics: metrics.clone(),
+        address: connection.ip(),
+        user_id: user_id.clone(),
+    };
+    // producer shuts down (closing the transport) when user is kicked
+    let configuration = Configuration {
+        shutdown: async move {
+            disconnect.notified().await;
+            sleep(DISCONNECT_DELAY).await;
+            ShutdownType::Shutdown
+        },
+        send_error_callback: DefaultSendErrorCallback {},
+        receive_error_callback: DefaultReceiveErrorCallback {},
+        timeout: None,
+        _error: PhantomData,
+    };
+    metrics.user_connected();
+    producer.produce(transport, configuration).await.unwrap();
+    metrics.user_disconnected();
+
+    // session might have been resumed, so user id could have changed
+    user_disconnected(user_id.load(Ordering::Relaxed), state).await;
+}
+
+async fn user_disconnected(id: usize, state: State) {
+    let (connection, grace_period) = {
+        let mut state = state.write().await;
+        let Some(connection) = state.disconnect_user(id) else {
+            return;
+        };
+        info!(
+            "User <{}> disconnected, session can be resumed for {:?}.",
+            state.users[&id].name, state.session_grace_period
+        );
+        (connection, state.session_grace_period)
+    };
+    spawn(async move {
+        sleep(grace_period).await;
+        if let Some(user) = state.write().await.expire_session(id, connection) {
+            info!("User <{}> session expired.", user.name);
+        }
+    });
+}
+
+/// Periodically mark users inactive for longer than `idle_timeout` as idle.
+async fn detect_idle_users(state: State, idle_timeout: Duration) {
+    loop {
+        sleep(HEARTBEAT_INTERVAL).await;
+        state.write().await.detect_idle(idle_timeout);
+    }
+}
+
+/// Periodically hide typing indicators that weren't refreshed in time.
+async fn expire_typing(state: State) {
+    loop {
+        sleep(TYPING_EXPIRY_INTERVAL).await;
+        state.write().await.expire_typing();
+    }
+}
+
+/// Resolves once Ctrl-C is pressed or SIGTERM is received.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        signal(SignalKind::terminate())
+            .expect("unable to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    select! {
+        result = ctrl_c() => result.expect("unable to listen for shutdown signal"),
+        _ = terminate => {}
+    }
+}
+
+/// Wait for shutdown signal, then warn connected users, stop accepting new connections,
+/// wait for in-flight calls and close all connections (until timeout passes).
+async fn shutdown(
+    state: State,
+    health: Arc<Health>,
+    metrics: Arc<Metrics>,
+    warning: Duration,
+    timeout: Duration,
+) {
+    shutdown_signal().await;
+    info!("Shutting down in {warning:?}...");
+    health.start_draining();
+    if !warning.is_zero() {
+        state.read().await.emit(ServerEvent::ShutdownWarning {
+            seconds: warning.as_secs(),
+        });
+        sleep(warning).await;
+    }
+
+    let deadline = Instant::now() + timeout;
+    if !wait_until(deadline, || metrics.rpcs_in_flight() == 0).await {
+        warn!(
+            "Closing connections with {} calls still in flight.",
+            metrics.rpcs_in_flight()
+        );
+    }
+    state.read().await.close_connections();
+    if !wait_until(deadline, || metrics.connected_users() == 0).await {
+        warn!(
+            "Shutdown timed out with {} users still connected.",
+            metrics.connected_users()
+        );
+    }
+}
+
+/// Wait until condition holds or deadline passes, return whether condition holds.
+async fn wait_until(deadline: Instant, condition: impl Fn() -> bool) -> bool {
+    while !condition() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        sleep(SHUTDOWN_POLL_INTERVAL).await;
+    }
+    true
+}
+
+/// Add account with password read from standard input.
+fn add_account(config: &Config, name: &str) -> Result<()> {
+    let mut accounts = Accounts::open(&config.accounts_file)?;
+    let mut password = String::new();
+    stdin()
+        .read_line(&mut password)
+        .context("unable to read password")?;
+    let credentials = Credentials {
+        name: name.to_string(),
+        password: password.trim_end_matches(['\r', '\n']).to_string(),
     };
-    producer
-        .produce(transport, Configuration::default())
+    accounts
+        .add(&credentials)
+        .with_context(|| format!("unable to add account <{name}>"))?;
+    info!("Account <{name}> added to {:?}.", config.accounts_file);
+    Ok(())
+}
+
+/// Register new account and respond with token issued for it.
+async fn register(
+    credentials: Credentials,
+    address: IpAddr,
+    accounts: SharedAccounts,
+    tokens: Arc<Tokens>,
+    state: State,
+) -> warp::reply::Response {
+    let name = credentials.name.clone();
+    if let Err(error) = check_ban(&state, &name, address).await {
+        warn!("Rejected registration of account <{name}> from {address}: {error}.");
+        return auth_reply(Err(error));
+    }
+    let result = spawn_blocking(move || accounts.blocking_lock().register(&credentials))
         .await
-        .unwrap();
+        .expect("account registration panicked");
+    match result {
+        Ok(()) => {
+            info!("Account <{name}> registered.");
+            auth_reply(Ok(tokens.issue(&name)))
+        }
+        Err(error) => auth_reply(Err(error)),
+    }
+}
 
-    user_disconnected(id, name, &state).await;
+/// Check credentials and respond with token issued for account.
+async fn login(
+    credentials: Credentials,
+    address: IpAddr,
+    accounts: SharedAccounts,
+    tokens: Arc<Tokens>,
+    state: State,
+) -> warp::reply::Response {
+    let mut result = spawn_blocking(move || accounts.blocking_lock().login(&credentials))
+        .await
+        .expect("login panicked");
+    if let Ok(account) = &result {
+        if let Err(error) = check_ban(&state, account, address).await {
+            result = Err(error);
+        }
+    }
+    if let Err(error) = &result {
+        warn!("Failed login attempt: {error}.");
+    }
+    auth_reply(result.map(|account| tokens.issue(&account)))
 }
 
-async fn user_disconnected(id: usize, name: String, state: &State) {
-    info!("User <{name}> disconnected.");
-    let mut state = state.write().await;
-    state.users.remove(&id);
-    let _ = state.disconnected_sender.send(name);
+fn auth_reply(result: Result<String, AuthError>) -> warp::reply::Response {
+    match result {
+        Ok(token) => warp::reply::json(&Token { token }).into_response(),
+        Err(error) => {
+            let status = match error {
+                AuthError::InvalidName(_) | AuthError::PasswordTooShort => StatusCode::BAD_REQUEST,
+                AuthError::AccountExists => StatusCode::CONFLICT,
+                AuthError::InvalidCredentials
+                | AuthError::MissingToken
+                | AuthError::InvalidToken => StatusCode::UNAUTHORIZED,
+                AuthError::Banned { .. } | AuthError::ReservedName => StatusCode::FORBIDDEN,
+                AuthError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            warp::reply::with_status(warp::reply::json(&error), status).into_response()
+        }
+    }
 }
 
-async fn handle_rejection(
-    err: warp::Rejection,
-) -> Result<impl warp::Reply, std::convert::Infallible> {
+/// Rejection of request without valid token.
+#[derive(Debug)]
+struct Unauthorized(AuthError);
+
+impl Reject for Unauthorized {}
+
+/// Extracts name of account from token passed in `Authorization: Bearer <token>` header
+/// or `token` query parameter, rejects request if token is missing or invalid.
+fn authenticated(
+    tokens: Arc<Tokens>,
+) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |header: Option<String>, query: HashMap<String, String>| {
+                let tokens = tokens.clone();
+                async move {
+                    header
+                        .as_deref()
+                        .and_then(|header| header.strip_prefix("Bearer "))
+                        .or(query.get("token").map(String::as_str))
+                        .ok_or(AuthError::MissingToken)
+                        .and_then(|token| tokens.verify(token))
+                        .map_err(|error| warp::reject::custom(Unauthorized(error)))
+                }
+            },
+        )
+}
+
+/// Check if account is not banned and address user connects from is not banned.
+async fn check_ban(state: &State, account: &str, address: IpAddr) -> Result<(), AuthError> {
+    match state.read().await.bans.find(account, address) {
+        Some(ban) => Err(AuthError::Banned { until: ban.until }),
+        None => Ok(()),
+    }
+}
+
+/// Rejects connection of banned account or from banned address.
+async fn not_banned(
+    account: String,
+    connection: ConnectionGuard,
+    state: State,
+) -> Result<(String, ConnectionGuard), Rejection> {
+    match check_ban(&state, &account, connection.ip()).await {
+        Ok(()) => Ok((account, connection)),
+        Err(error) => Err(warp::reject::custom(Unauthorized(error))),
+    }
+}
+
+/// Rejection of connection while server is shutting down.
+#[derive(Debug)]
+struct ShuttingDown;
+
+impl Reject for ShuttingDown {}
+
+/// Rejects request if server is shutting down.
+fn accepting_connections(
+    health: Arc<Health>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let draining = health.is_draining();
+            async move {
+                if draining {
+                    Err(warp::reject::custom(ShuttingDown))
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Rejection of connection from address with too many connections already.
+#[derive(Debug)]
+struct TooManyConnections;
+
+impl Reject for TooManyConnections {}
+
+/// Registers connection from remote address, rejects request
+/// if there are too many connections from that address already.
+fn connection_slot(
+    limits: Arc<Limits>,
+) -> impl Filter<Extract = (ConnectionGuard,), Error = Rejection> + Clone {
+    remote_ip().and_then(move |ip: IpAddr| {
+        let limits = limits.clone();
+        async move {
+            limits.connections.acquire(ip).ok_or_else(|| {
+                warn!("Rejected connection from {ip}: too many connections.");
+                warp::reject::custom(TooManyConnections)
+            })
+        }
+    })
+}
+
+/// Reason why request to `/ws` was rejected, for metrics.
+fn websocket_rejection(rejection: &Rejection) -> WebSocketRejection {
+    if let Some(Unauthorized(error)) = rejection.find() {
+        match error {
+            AuthError::Banned { .. } => WebSocketRejection::Banned,
+            _ => WebSocketRejection::Auth,
+        }
+    } else if rejection.find::<TooManyConnections>().is_some() {
+        WebSocketRejection::Limit
+    } else if rejection.find::<ShuttingDown>().is_some() {
+        WebSocketRejection::Draining
+    } else {
+        WebSocketRejection::Upgrade
+    }
+}
+
+/// Extracts remote IP address (unspecified address if it is unknown).
+fn remote_ip() -> impl Filter<Extract = (IpAddr,), Error = Infallible> + Clone {
+    warp::addr::remote().map(|address: Option<SocketAddr>| {
+        address.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |address| address.ip())
+    })
+}
+
+/// Stream of server events.
+async fn server_events(state: &State, metrics: &Arc<Metrics>) -> impl Stream<Item = ServerEvent> {
+    broadcast_stream(
+        state.read().await.events_sender.subscribe(),
+        metrics.clone(),
+        |missed| Some(ServerEvent::Gap { missed }),
+    )
+}
+
+/// Stream of values received from broadcast channel, values skipped by lagging receiver
+/// are counted and reported by value returned from `gap` (if any).
+fn broadcast_stream<T: Clone + Send + 'static>(
+    receiver: Receiver<T>,
+    metrics: Arc<Metrics>,
+    gap: impl Fn(u64) -> Option<T>,
+) -> impl Stream<Item = T> {
+    BroadcastStream::new(receiver).filter_map(move |result| match result {
+        Ok(value) => Some(value),
+        Err(BroadcastStreamRecvError::Lagged(count)) => {
+            metrics.messages_lagged(count);
+            gap(count)
+        }
+    })
+}
+
+/// Receiver that is already closed, for streams that should end immediately.
+fn closed_receiver<T: Clone>() -> Receiver<T> {
+    broadcast::channel(1).1
+}
+
+/// Stream of messages received from room message channel.
+///
+/// When receiver lags behind, skipped messages are loaded from storage if `backfill` is set,
+/// otherwise [MessageEvent::Gap] is yielded.
+fn room_messages(
+    state: State,
+    metrics: Arc<Metrics>,
+    room: String,
+    receiver: Receiver<ChatMessage>,
+    last_id: u64,
+    backfill: bool,
+) -> impl Stream<Item = MessageEvent> {
+    let stream = futures::stream::unfold(
+        (receiver, VecDeque::<ChatMessage>::new(), last_id),
+        move |(mut receiver, mut missed, mut last_id)| {
+            let state = state.clone();
+            let metrics = metrics.clone();
+            let room = room.clone();
+            async move {
+                loop {
+                    if let Some(message) = missed.pop_front() {
+                        metrics.message_delivered();
+                        last_id = message.id;
+                        let event = MessageEvent::Message(message);
+                        return Some((event, (receiver, missed, last_id)));
+                    }
+                    match receiver.recv().await {
+                        // skip messages that were already backfilled
+                        Ok(message) if message.id > last_id => {
+                            metrics.message_delivered();
+                            last_id = message.id;
+                            let event = MessageEvent::Message(message);
+                            return Some((event, (receiver, missed, last_id)));
+                        }
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(count)) => {
+                            warn!("Subscriber of room #{room} lagged behind by {count} messages.");
+                            metrics.messages_lagged(count);
+                            if backfill {
+                                let state = state.read().await;
+                                let messages = state.storage.after(&room, last_id, count as usize);
+                                missed = state.with_reactions(messages).into();
+                            } else {
+                                let event = MessageEvent::Gap { missed: count };
+                                return Some((event, (receiver, missed, last_id)));
+                            }
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        },
+    );
+    Box::pin(stream)
+}
+
+/// Resolves once user with given id leaves the room (or the room is removed).
+async fn user_left(mut left: Receiver<(usize, String)>, user_id: usize) {
+    loop {
+        match left.recv().await {
+            Ok((id, _)) if id == user_id => break,
+            Err(RecvError::Closed) => break,
+            _ => {}
+        }
+    }
+}
+
+async fn handle_rejection(err: Rejection) -> Result<warp::reply::Response, Infallible> {
     if err.is_not_found() {
         error!("Error occurred: {:?}.", err);
-        Ok(warp::reply::with_status("Not found", StatusCode::NOT_FOUND))
+        Ok(warp::reply::with_status("Not found", StatusCode::NOT_FOUND).into_response())
+    } else if let Some(Unauthorized(error)) = err.find() {
+        warn!("Rejected unauthenticated connection: {error}.");
+        Ok(auth_reply(Err(error.clone())))
+    } else if err.find::<ShuttingDown>().is_some() {
+        Ok(
+            warp::reply::with_status("Server is shutting down", StatusCode::SERVICE_UNAVAILABLE)
+                .into_response(),
+        )
+    } else if err.find::<TooManyConnections>().is_some() {
+        Ok(
+            warp::reply::with_status("Too many connections", StatusCode::TOO_MANY_REQUESTS)
+                .into_response(),
+        )
+    } else if err
+        .find::<warp::filters::body::BodyDeserializeError>()
+        .is_some()
+    {
+        Ok(warp::reply::with_status("Bad request", StatusCode::BAD_REQUEST).into_response())
     } else {
         error!("Error occurred: {:?}.", err);
-        Ok(warp::reply::with_status(
-            "Internal server error",
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
+        Ok(
+            warp::reply::with_status("Internal server error", StatusCode::INTERNAL_SERVER_ERROR)
+                .into_response(),
+        )
     }
 }
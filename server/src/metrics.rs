@@ -0,0 +1,286 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Upper bounds (in seconds) of RPC latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    /// Number of observations falling into each bucket (not cumulative).
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(index) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[index] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// Reason why request to `/ws` was rejected before upgrading to WebSocket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebSocketRejection {
+    /// Token is missing or invalid.
+    Auth,
+    /// Account or address is banned.
+    Banned,
+    /// Too many connections from address.
+    Limit,
+    /// Server is shutting down.
+    Draining,
+    /// Request is not a valid WebSocket upgrade request.
+    Upgrade,
+}
+
+impl WebSocketRejection {
+    const ALL: [WebSocketRejection; 5] = [
+        WebSocketRejection::Auth,
+        WebSocketRejection::Banned,
+        WebSocketRejection::Limit,
+        WebSocketRejection::Draining,
+        WebSocketRejection::Upgrade,
+    ];
+
+    /// Value of `reason` label.
+    fn label(self) -> &'static str {
+        match self {
+            WebSocketRejection::Auth => "auth",
+            WebSocketRejection::Banned => "banned",
+            WebSocketRejection::Limit => "limit",
+            WebSocketRejection::Draining => "draining",
+            WebSocketRejection::Upgrade => "upgrade",
+        }
+    }
+}
+
+/// Server metrics exposed in Prometheus text exposition format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Number of users with open transport.
+    connected_users: AtomicI64,
+    /// Messages accepted from users (room and direct).
+    messages_sent: AtomicU64,
+    /// Messages delivered to subscribers (room and direct).
+    messages_delivered: AtomicU64,
+    /// Messages and events skipped by subscribers lagging behind their channel.
+    lagged_messages: AtomicU64,
+    /// Requests to `/ws` that were rejected before upgrading to WebSocket,
+    /// by reason (indexed as [WebSocketRejection::ALL]).
+    websocket_rejections: [AtomicU64; WebSocketRejection::ALL.len()],
+    /// Number of RPC calls currently being handled.
+    rpcs_in_flight: AtomicUsize,
+    /// RPC latencies by `chat::Api` method name.
+    rpc_latencies: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+impl Metrics {
+    pub fn user_connected(&self) {
+        self.connected_users.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn user_disconnected(&self) {
+        self.connected_users.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn message_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn message_delivered(&self) {
+        self.messages_delivered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record given number of messages or events skipped by lagging subscriber.
+    pub fn messages_lagged(&self, count: u64) {
+        self.lagged_messages.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn websocket_rejected(&self, reason: WebSocketRejection) {
+        self.websocket_rejections[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get number of users with open transport.
+    pub fn connected_users(&self) -> i64 {
+        self.connected_users.load(Ordering::Relaxed)
+    }
+
+    /// Get number of RPC calls currently being handled.
+    pub fn rpcs_in_flight(&self) -> usize {
+        self.rpcs_in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Start measuring call of RPC method with given name,
+    /// call is recorded once returned timer is dropped.
+    pub fn rpc(&self, method: &'static str) -> RpcTimer<'_> {
+        self.rpcs_in_flight.fetch_add(1, Ordering::Relaxed);
+        RpcTimer {
+            metrics: self,
+            method,
+            start: Instant::now(),
+        }
+    }
+
+    fn observe_rpc(&self, method: &'static str, duration: Duration) {
+        self.rpcs_in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.rpc_latencies
+            .lock()
+            .unwrap()
+            .entry(method)
+            .or_default()
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Render metrics in Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(output, "# HELP {name} {help}");
+            let _ = writeln!(output, "# TYPE {name} {kind}");
+            let _ = writeln!(output, "{name} {value}");
+        };
+        metric(
+            "chat_connected_users",
+            "gauge",
+            "Number of users with open connection.",
+            self.connected_users().to_string(),
+        );
+        metric(
+            "chat_messages_sent_total",
+            "counter",
+            "Number of room and direct messages sent by users.",
+            self.messages_sent.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "chat_messages_delivered_total",
+            "counter",
+            "Number of room and direct messages delivered to subscribers.",
+            self.messages_delivered.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "chat_lagged_messages_total",
+            "counter",
+            "Number of messages and events skipped by subscribers lagging behind.",
+            self.lagged_messages.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "chat_rpcs_in_flight",
+            "gauge",
+            "Number of chat API calls currently being handled.",
+            self.rpcs_in_flight().to_string(),
+        );
+
+        let name = "chat_websocket_rejections_total";
+        let _ = writeln!(
+            output,
+            "# HELP {name} Number of rejected WebSocket connection requests by reason."
+        );
+        let _ = writeln!(output, "# TYPE {name} counter");
+        for (reason, count) in WebSocketRejection::ALL
+            .iter()
+            .zip(&self.websocket_rejections)
+        {
+            let _ = writeln!(
+                output,
+                "{name}{{reason=\"{}\"}} {}",
+                reason.label(),
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        let name = "chat_rpc_duration_seconds";
+        let _ = writeln!(
+            output,
+            "# HELP {name} Duration of chat API calls by method."
+        );
+        let _ = writeln!(output, "# TYPE {name} histogram");
+        for (method, histogram) in self.rpc_latencies.lock().unwrap().iter() {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    output,
+                    "{name}_bucket{{method=\"{method}\",le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                output,
+                "{name}_bucket{{method=\"{method}\",le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(
+                output,
+                "{name}_sum{{method=\"{method}\"}} {}",
+                histogram.sum
+            );
+            let _ = writeln!(
+                output,
+                "{name}_count{{method=\"{method}\"}} {}",
+                histogram.count
+            );
+        }
+        output
+    }
+}
+
+/// Measures duration of RPC call, see [Metrics::rpc].
+pub struct RpcTimer<'a> {
+    metrics: &'a Metrics,
+    method: &'static str,
+    start: Instant,
+}
+
+impl Drop for RpcTimer<'_> {
+    fn drop(&mut self) {
+        self.metrics.observe_rpc(self.method, self.start.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Metrics, WebSocketRejection};
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.message_sent();
+        metrics.message_sent();
+        metrics.websocket_rejected(WebSocketRejection::Banned);
+        let timer = metrics.rpc("rooms");
+        assert_eq!(metrics.rpcs_in_flight(), 1);
+        drop(timer);
+        assert_eq!(metrics.rpcs_in_flight(), 0);
+        for duration in [Duration::from_millis(2), Duration::from_secs(10)] {
+            let mut timer = metrics.rpc("message");
+            timer.start -= duration;
+        }
+
+        let output = metrics.render();
+        assert!(output.contains("# TYPE chat_messages_sent_total counter\n"));
+        assert!(output.contains("\nchat_messages_sent_total 2\n"));
+        assert!(output.contains("\nchat_connected_users 0\n"));
+        assert!(output.contains("\nchat_websocket_rejections_total{reason=\"banned\"} 1\n"));
+        assert!(output.contains("\nchat_websocket_rejections_total{reason=\"auth\"} 0\n"));
+        assert!(output
+            .contains("\nchat_rpc_duration_seconds_bucket{method=\"message\",le=\"0.001\"} 0\n"));
+        assert!(output
+            .contains("\nchat_rpc_duration_seconds_bucket{method=\"message\",le=\"0.005\"} 1\n"));
+        assert!(output
+            .contains("\nchat_rpc_duration_seconds_bucket{method=\"message\",le=\"+Inf\"} 2\n"));
+        assert!(output.contains("\nchat_rpc_duration_seconds_count{method=\"message\"} 2\n"));
+        assert!(output.contains("\nchat_rpc_duration_seconds_count{method=\"rooms\"} 1\n"));
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Account or address ban applies to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BanTarget {
+    /// Lowercase account name.
+    Account(String),
+    Address(IpAddr),
+}
+
+impl BanTarget {
+    /// Interpret given string as IP address or (if it isn't one) as account name.
+    pub fn parse(target: &str) -> Self {
+        target
+            .parse()
+            .map(BanTarget::Address)
+            .unwrap_or_else(|_| BanTarget::Account(target.to_lowercase()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ban {
+    pub target: BanTarget,
+    /// `None` if ban is permanent.
+    pub until: Option<DateTime<Utc>>,
+    pub reason: String,
+    /// Account of admin who issued the ban.
+    pub by: String,
+}
+
+impl Ban {
+    /// Check if ban applies to user with given account connected from given address.
+    pub fn applies_to(&self, account: &str, address: IpAddr) -> bool {
+        match &self.target {
+            BanTarget::Account(banned) => banned == &account.to_lowercase(),
+            BanTarget::Address(banned) => banned == &address,
+        }
+    }
+
+    fn is_active_at(&self, now: DateTime<Utc>) -> bool {
+        self.until.is_none_or(|until| until > now)
+    }
+}
+
+/// Active bans, optionally persisted to a file (one JSON object per line).
+///
+/// File is rewritten (without expired bans) every time bans change.
+#[derive(Debug, Default)]
+pub struct Bans {
+    bans: Vec<Ban>,
+    path: Option<PathBuf>,
+}
+
+impl Bans {
+    /// Load bans from file (if it exists), changes will be saved to it.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut bans = vec![];
+        if path.exists() {
+            let file =
+                File::open(path).with_context(|| format!("unable to open bans file {path:?}"))?;
+            let now = Utc::now();
+            for (number, line) in BufReader::new(file).lines().enumerate() {
+                let line = line.with_context(|| format!("unable to read bans file {path:?}"))?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let ban: Ban = serde_json::from_str(&line).with_context(|| {
+                    format!("invalid ban in bans file {path:?} at line {}", number + 1)
+                })?;
+                if ban.is_active_at(now) {
+                    bans.push(ban);
+                }
+            }
+        }
+        Ok(Bans {
+            bans,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    /// Find active ban applying to user with given account connected from given address.
+    pub fn find(&self, account: &str, address: IpAddr) -> Option<&Ban> {
+        let now = Utc::now();
+        self.bans
+            .iter()
+            .find(|ban| ban.is_active_at(now) && ban.applies_to(account, address))
+    }
+
+    /// Add ban, replacing previous ban of the same target.
+    ///
+    /// Ban is kept in memory even if it couldn't be saved.
+    pub fn add(&mut self, ban: Ban) -> Result<()> {
+        self.bans.retain(|existing| existing.target != ban.target);
+        self.bans.push(ban);
+        self.save()
+    }
+
+    /// Remove ban of given target, return `false` if target wasn't banned.
+    pub fn remove(&mut self, target: &BanTarget) -> Result<bool> {
+        let count = self.bans.len();
+        self.bans.retain(|ban| &ban.target != target);
+        if self.bans.len() == count {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn save(&mut self) -> Result<()> {
+        let now = Utc::now();
+        self.bans.retain(|ban| ban.is_active_at(now));
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // write to temporary file first, so bans are not lost if writing fails
+        let temporary_path = path.with_extension("tmp");
+        let file = File::create(&temporary_path)
+            .with_context(|| format!("unable to create file {temporary_path:?}"))?;
+        let mut writer = BufWriter::new(file);
+        for ban in &self.bans {
+            serde_json::to_writer(&mut writer, ban)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        fs::rename(&temporary_path, path)
+            .with_context(|| format!("unable to write bans file {path:?}"))
+    }
+}
+
+/// Describe duration of ban or mute, like "for 1h 30m" or "permanently".
+pub fn format_duration(duration: Option<Duration>) -> String {
+    let Some(duration) = duration else {
+        return "permanently".to_string();
+    };
+    let mut seconds = duration.as_secs();
+    if seconds == 0 {
+        return "for 0s".to_string();
+    }
+    let mut parts = vec![];
+    for (unit, length) in [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)] {
+        if seconds >= length {
+            parts.push(format!("{}{unit}", seconds / length));
+            seconds %= length;
+        }
+    }
+    format!("for {}", parts.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env::temp_dir,
+        fs::remove_file,
+        net::{IpAddr, Ipv4Addr},
+        process,
+        time::Duration,
+    };
+
+    use chrono::Utc;
+
+    use super::{format_duration, Ban, BanTarget, Bans};
+
+    #[test]
+    fn test_bans() {
+        let path = temp_dir().join(format!("bans-test-{}.jsonl", process::id()));
+        let address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let other_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        {
+            let mut bans = Bans::open(&path).unwrap();
+            bans.add(Ban {
+                target: BanTarget::parse("Mallory"),
+                until: None,
+                reason: "spam".to_string(),
+                by: "Alice".to_string(),
+            })
+            .unwrap();
+            bans.add(Ban {
+                target: BanTarget::parse("10.0.0.1"),
+                until: Some(Utc::now() - chrono::Duration::seconds(1)),
+                reason: "expired".to_string(),
+                by: "Alice".to_string(),
+            })
+            .unwrap();
+            assert!(bans.find("mallory", other_address).is_some());
+            assert!(bans.find("Bob", address).is_none());
+        }
+        let mut bans = Bans::open(&path).unwrap();
+        assert!(bans.find("Mallory", other_address).is_some());
+        assert!(bans.remove(&BanTarget::parse("MALLORY")).unwrap());
+        assert!(!bans.remove(&BanTarget::parse("Mallory")).unwrap());
+        let bans = Bans::open(&path).unwrap();
+        remove_file(&path).unwrap();
+        assert!(bans.find("Mallory", other_address).is_none());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(None), "permanently");
+        assert_eq!(
+            format_duration(Some(Duration::from_secs(5400))),
+            "for 1h 30m"
+        );
+        assert_eq!(
+            format_duration(Some(Duration::from_secs(86401))),
+            "for 1d 1s"
+        );
+    }
+}
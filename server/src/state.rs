@@ -1,24 +1,150 @@
 use std::{
-    collections::HashMap,
-    sync::atomic::{AtomicUsize, Ordering},
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
-use tokio::sync::broadcast::{self, Sender};
+use chrono::{DateTime, Utc};
+use common::api::chat::{
+    validate_emoji, validate_room_name, validate_user_name, BanScope, ChatError, ChatMessage,
+    MessageEvent, Presence, PresenceStatus, Reaction, Revision, Role, ServerEvent, Typing,
+    DEFAULT_ROOM, MAX_HISTORY_LIMIT, MAX_TOPIC_LENGTH, MAX_USER_NAME_LENGTH, SERVER_USER_NAME,
+    TYPING_TIMEOUT,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use tokio::sync::{
+    broadcast::{self, Sender},
+    Notify,
+};
+use tracing::error;
+
+use crate::{
+    moderation::{format_duration, Ban, BanTarget, Bans},
+    storage::{ReactionChange, Storage},
+};
+
+/// Id messages sent by server are attributed to.
+const SERVER_USER_ID: usize = 0;
 
 static NEXT_USER_ID: AtomicUsize = AtomicUsize::new(1);
 
 #[derive(Debug)]
 pub struct User {
     pub id: usize,
+    /// Name of account user authenticated with.
+    pub account: String,
     pub name: String,
+    pub role: Role,
+    /// Address user is connected from.
+    pub address: IpAddr,
+    /// Secret token used to resume session after reconnecting.
+    pub token: String,
+    /// Whether user's transport is currently open.
+    pub connected: bool,
+    /// Whether connection of user was announced to others.
+    pub announced: bool,
+    /// Status reported while user is connected.
+    pub status: PresenceStatus,
+    /// Last time user sent a message, reported activity or disconnected.
+    pub last_active: DateTime<Utc>,
+    /// Incremented every time session is resumed.
+    pub connection: u64,
+    /// Pairs containing: (sender name, message)
+    pub inbox_sender: Sender<(String, String)>,
+    /// Events sent only to this user.
+    pub events_sender: Sender<ServerEvent>,
+    /// Notified when user's transport should be closed.
+    pub disconnect: Arc<Notify>,
+    /// Whether user was kicked or banned, its session can't be resumed
+    /// and it is removed once its transport is closed.
+    pub kicked: bool,
 }
 
 impl User {
-    pub fn new() -> Self {
+    pub fn new(
+        account: String,
+        name: String,
+        role: Role,
+        address: IpAddr,
+        channel_capacity: usize,
+    ) -> Self {
         let id = NEXT_USER_ID.fetch_add(1, Ordering::Relaxed);
+        let token = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
         User {
             id,
-            name: format!("User {id}"),
+            account,
+            name,
+            role,
+            address,
+            token,
+            connected: true,
+            announced: false,
+            status: PresenceStatus::Online,
+            last_active: Utc::now(),
+            connection: 0,
+            inbox_sender: broadcast::channel(channel_capacity).0,
+            events_sender: broadcast::channel(channel_capacity).0,
+            disconnect: Arc::new(Notify::new()),
+            kicked: false,
+        }
+    }
+
+    pub fn presence(&self) -> Presence {
+        Presence {
+            user: self.name.clone(),
+            status: if self.connected {
+                self.status
+            } else {
+                PresenceStatus::Offline
+            },
+            last_seen: self.last_active,
+        }
+    }
+}
+
+/// Capacities of broadcast channels.
+#[derive(Debug, Clone, Copy)]
+pub struct Capacities {
+    /// Capacity of event channels.
+    pub events: usize,
+
+    /// Capacity of room message channels.
+    pub messages: usize,
+}
+
+#[derive(Debug)]
+pub struct Room {
+    pub name: String,
+    pub members: HashSet<usize>,
+    /// Empty if not set.
+    pub topic: String,
+    pub message_sender: Sender<ChatMessage>,
+    /// Edits, deletions and reaction changes of room messages.
+    pub update_sender: Sender<MessageEvent>,
+    /// Pairs containing: (user id, user name)
+    pub joined_sender: Sender<(usize, String)>,
+    /// Pairs containing: (user id, user name)
+    pub left_sender: Sender<(usize, String)>,
+}
+
+impl Room {
+    pub fn new(name: String, capacities: Capacities) -> Self {
+        Room {
+            name,
+            members: HashSet::new(),
+            topic: String::new(),
+            message_sender: broadcast::channel(capacities.messages).0,
+            update_sender: broadcast::channel(capacities.events).0,
+            joined_sender: broadcast::channel(capacities.events).0,
+            left_sender: broadcast::channel(capacities.events).0,
         }
     }
 }
@@ -26,26 +152,1592 @@ impl User {
 #[derive(Debug)]
 pub struct State {
     pub users: HashMap<usize, User>,
-    pub message_sender: Sender<(String, String)>,
-    pub connected_sender: Sender<String>,
-    pub disconnected_sender: Sender<String>,
+    pub rooms: HashMap<String, Room>,
+    pub events_sender: Sender<ServerEvent>,
+    pub presence_sender: Sender<Presence>,
+    /// Pairs containing: (user id, typing indicator change)
+    pub typing_sender: Sender<(usize, Typing)>,
+    pub storage: Box<dyn Storage>,
+    /// How long disconnected user's session can be resumed.
+    pub session_grace_period: Duration,
+    pub bans: Bans,
+    /// Roles of accounts (by lowercase account name) other than [Role::User].
+    roles: HashMap<String, Role>,
+    /// Muted accounts (by lowercase account name) mapped to time mute ends
+    /// (`None` if user is muted until unmuted).
+    mutes: HashMap<String, Option<Instant>>,
+    /// Session tokens mapped to user ids.
+    sessions: HashMap<String, usize>,
+    /// Users that left (by lowercase name) mapped to time they were last seen.
+    last_seen: HashMap<String, DateTime<Utc>>,
+    /// Pairs containing (user id, room name) of users typing mapped to time indicator expires.
+    typing: HashMap<(usize, String), Instant>,
+    next_message_id: u64,
+    capacities: Capacities,
 }
 
 impl State {
-    pub fn new() -> Self {
+    pub fn new(
+        capacities: Capacities,
+        storage: Box<dyn Storage>,
+        session_grace_period: Duration,
+        roles: HashMap<String, Role>,
+        bans: Bans,
+    ) -> Self {
+        let mut rooms = HashMap::new();
+        rooms.insert(
+            DEFAULT_ROOM.to_string(),
+            Room::new(DEFAULT_ROOM.to_string(), capacities),
+        );
         State {
             users: HashMap::new(),
-            message_sender: broadcast::channel(10).0,
-            connected_sender: broadcast::channel(10).0,
-            disconnected_sender: broadcast::channel(10).0,
+            rooms,
+            events_sender: broadcast::channel(capacities.events).0,
+            presence_sender: broadcast::channel(capacities.events).0,
+            typing_sender: broadcast::channel(capacities.events).0,
+            next_message_id: storage.last_id() + 1,
+            storage,
+            session_grace_period,
+            bans,
+            roles,
+            mutes: HashMap::new(),
+            sessions: HashMap::new(),
+            last_seen: HashMap::new(),
+            typing: HashMap::new(),
+            capacities,
         }
     }
 
-    /// Add new user and return reference to it.
-    pub fn add_user(&mut self) -> &User {
-        let user = User::new();
+    /// Add new user authenticated with given account and connected from given address,
+    /// make it join default room and return reference to it.
+    ///
+    /// User is named after the account, with numeric suffix appended
+    /// if the name is already taken. Connection is not announced until [State::announce_user]
+    /// is called, as the user might be discarded when another session is resumed.
+    pub fn add_user(&mut self, account: &str, address: IpAddr) -> &User {
+        let mut name = account.to_string();
+        let mut number = 1;
+        while self.is_name_taken(&name) {
+            number += 1;
+            let suffix = format!("-{number}");
+            let prefix: String = account
+                .chars()
+                .take(MAX_USER_NAME_LENGTH - suffix.len())
+                .collect();
+            name = format!("{}{suffix}", prefix.trim_end());
+        }
+        let user = User::new(
+            account.to_string(),
+            name,
+            self.role(account),
+            address,
+            self.capacities.events,
+        );
         let id = user.id;
+        self.sessions.insert(user.token.clone(), id);
         self.users.insert(user.id, user);
+        let _ = self.join_room(id, DEFAULT_ROOM);
         &self.users[&id]
     }
+
+    /// Notify others about connection of user with given id, unless it was already announced.
+    pub fn announce_user(&mut self, id: usize) {
+        let Some(user) = self.users.get_mut(&id) else {
+            return;
+        };
+        if !user.announced {
+            user.announced = true;
+            let event = ServerEvent::UserJoined {
+                user: user.name.clone(),
+            };
+            self.last_seen.remove(&user.name.to_lowercase());
+            self.emit(event);
+        }
+    }
+
+    /// Remove user, make it leave all its rooms and notify others.
+    pub fn remove_user(&mut self, id: usize) -> Option<User> {
+        let user = self.discard_user(id)?;
+        if user.announced {
+            self.last_seen
+                .insert(user.name.to_lowercase(), user.last_active);
+            self.emit(ServerEvent::UserLeft {
+                user: user.name.clone(),
+            });
+        }
+        Some(user)
+    }
+
+    /// Remove user and make it leave all its rooms without announcing disconnection.
+    fn discard_user(&mut self, id: usize) -> Option<User> {
+        for room in self.user_rooms(id) {
+            let _ = self.leave_room(id, &room);
+        }
+        let user = self.users.remove(&id)?;
+        self.sessions.remove(&user.token);
+        Some(user)
+    }
+
+    /// Get user with given id, fails if it was kicked or its session was closed
+    /// in the meantime.
+    pub fn user(&self, id: usize) -> Result<&User, ChatError> {
+        self.users
+            .get(&id)
+            .filter(|user| !user.kicked)
+            .ok_or(ChatError::SessionClosed)
+    }
+
+    /// Get names of rooms user with given id is member of.
+    pub fn user_rooms(&self, id: usize) -> Vec<String> {
+        self.rooms
+            .values()
+            .filter(|room| room.members.contains(&id))
+            .map(|room| room.name.clone())
+            .collect()
+    }
+
+    /// Mark user as disconnected and return its connection number
+    /// (session can be resumed until grace period passes).
+    ///
+    /// Kicked users are removed right away and `None` is returned.
+    pub fn disconnect_user(&mut self, id: usize) -> Option<u64> {
+        let user = self.users.get_mut(&id)?;
+        if user.kicked {
+            self.remove_user(id);
+            return None;
+        }
+        user.connected = false;
+        user.last_active = Utc::now();
+        let connection = user.connection;
+        self.emit_presence(id);
+        for room in self.user_rooms(id) {
+            self.stop_typing(id, &room);
+        }
+        Some(connection)
+    }
+
+    /// Remove user if it is still disconnected since given connection.
+    pub fn expire_session(&mut self, id: usize, connection: u64) -> Option<User> {
+        let user = self.users.get(&id)?;
+        if user.connected || user.connection != connection {
+            return None;
+        }
+        self.remove_user(id)
+    }
+
+    /// Restore session identified by token for connection currently serving
+    /// user with id `current_id`, return id of resumed user.
+    ///
+    /// User created for current connection is discarded. Session can only be resumed
+    /// by connection authenticated with the same account.
+    pub fn resume_session(&mut self, current_id: usize, token: &str) -> Result<usize, ChatError> {
+        let id = *self
+            .sessions
+            .get(token)
+            .ok_or(ChatError::InvalidSessionToken)?;
+        if id == current_id {
+            return Ok(id);
+        }
+        let account = self.user(current_id)?.account.clone();
+        let user = self
+            .users
+            .get_mut(&id)
+            .ok_or(ChatError::InvalidSessionToken)?;
+        if user.account != account || user.kicked {
+            return Err(ChatError::InvalidSessionToken);
+        }
+        if user.connected {
+            return Err(ChatError::SessionInUse);
+        }
+        user.connected = true;
+        user.connection += 1;
+        // resumed user is now served by current connection
+        let current_user = &self.users[&current_id];
+        let (address, disconnect) = (current_user.address, current_user.disconnect.clone());
+        let user = self.users.get_mut(&id).unwrap();
+        user.address = address;
+        user.disconnect = disconnect;
+        self.discard_user(current_id);
+        self.emit_presence(id);
+        Ok(id)
+    }
+
+    /// Get pairs containing: (room name, event) with messages sent to rooms user with given id
+    /// is member of after message with given id, oldest first.
+    ///
+    /// At most [MAX_HISTORY_LIMIT] latest messages are returned per room,
+    /// skipped messages are reported by [MessageEvent::Gap] before all messages.
+    pub fn missed_messages(
+        &self,
+        id: usize,
+        last_seen_message_id: u64,
+    ) -> Vec<(String, MessageEvent)> {
+        let mut gaps = vec![];
+        let mut messages = vec![];
+        for room in self.user_rooms(id) {
+            let mut missed = self.storage.after(&room, last_seen_message_id, usize::MAX);
+            let skipped = missed.len().saturating_sub(MAX_HISTORY_LIMIT);
+            if skipped > 0 {
+                let event = MessageEvent::Gap {
+                    missed: skipped as u64,
+                };
+                gaps.push((room.clone(), event));
+            }
+            messages.extend(missed.drain(skipped..));
+        }
+        messages.sort_by_key(|message| message.id);
+        let messages = self
+            .with_reactions(messages)
+            .into_iter()
+            .map(|message| (message.room.clone(), MessageEvent::Message(message)));
+        gaps.into_iter().chain(messages).collect()
+    }
+
+    /// Find user by name (case insensitive), including disconnected user
+    /// whose session can still be resumed.
+    pub fn session_by_name(&self, name: &str) -> Option<&User> {
+        let name = name.to_lowercase();
+        self.users
+            .values()
+            .find(|user| user.name.to_lowercase() == name)
+    }
+
+    /// Find connected user by name (case insensitive).
+    pub fn user_by_name(&self, name: &str) -> Option<&User> {
+        self.session_by_name(name).filter(|user| user.connected)
+    }
+
+    /// Check if name is used by any user (case insensitive), names of disconnected users
+    /// are kept until their sessions expire.
+    pub fn is_name_taken(&self, name: &str) -> bool {
+        self.session_by_name(name).is_some()
+    }
+
+    /// Change name of user with given id and return its previous name.
+    pub fn rename_user(&mut self, id: usize, name: String) -> Result<String, ChatError> {
+        validate_user_name(&name)?;
+        let current_name = &self.user(id)?.name;
+        if current_name == &name {
+            return Ok(name);
+        }
+        if current_name.to_lowercase() != name.to_lowercase() && self.is_name_taken(&name) {
+            return Err(ChatError::NameTaken);
+        }
+        let user = self.users.get_mut(&id).unwrap();
+        let old_name = std::mem::replace(&mut user.name, name.clone());
+        self.emit(ServerEvent::UserRenamed {
+            old_name: old_name.clone(),
+            new_name: name,
+        });
+        Ok(old_name)
+    }
+
+    /// Send private message from user with given id to user with given name.
+    pub fn direct_message(
+        &mut self,
+        id: usize,
+        to: &str,
+        message: String,
+    ) -> Result<(), ChatError> {
+        let sender = self.user(id)?;
+        if self.is_muted(&sender.account) {
+            return Err(ChatError::Muted);
+        }
+        let recipient = self.user_by_name(to).ok_or(ChatError::UnknownUser)?;
+        let sender_name = sender.name.clone();
+        let _ = recipient.inbox_sender.send((sender_name, message));
+        self.user_active(id);
+        Ok(())
+    }
+
+    /// Record activity of user with given id, user is no longer idle.
+    pub fn user_active(&mut self, id: usize) {
+        let Some(user) = self.users.get_mut(&id) else {
+            return;
+        };
+        user.last_active = Utc::now();
+        if user.status == PresenceStatus::Idle {
+            user.status = PresenceStatus::Online;
+            self.emit_presence(id);
+        }
+    }
+
+    /// Set status of user with given id (only statuses that can be chosen by user are allowed).
+    pub fn set_status(&mut self, id: usize, status: PresenceStatus) -> Result<(), ChatError> {
+        if matches!(status, PresenceStatus::Idle | PresenceStatus::Offline) {
+            return Err(ChatError::InvalidStatus);
+        }
+        let user = self.users.get_mut(&id).ok_or(ChatError::SessionClosed)?;
+        user.last_active = Utc::now();
+        if user.status != status {
+            user.status = status;
+            self.emit_presence(id);
+        }
+        Ok(())
+    }
+
+    /// Mark online users inactive for longer than `timeout` as idle.
+    pub fn detect_idle(&mut self, timeout: Duration) {
+        let Ok(timeout) = chrono::Duration::from_std(timeout) else {
+            return;
+        };
+        let idle_since = Utc::now() - timeout;
+        let idle: Vec<usize> = self
+            .users
+            .values()
+            .filter(|user| {
+                user.connected
+                    && user.status == PresenceStatus::Online
+                    && user.last_active < idle_since
+            })
+            .map(|user| user.id)
+            .collect();
+        for id in idle {
+            self.users.get_mut(&id).unwrap().status = PresenceStatus::Idle;
+            self.emit_presence(id);
+        }
+    }
+
+    /// Get presence of user with given name (case insensitive),
+    /// users that already left are reported as offline.
+    pub fn user_presence(&self, name: &str) -> Result<Presence, ChatError> {
+        if let Some(user) = self.session_by_name(name) {
+            return Ok(user.presence());
+        }
+        self.last_seen
+            .get(&name.to_lowercase())
+            .map(|last_seen| Presence {
+                user: name.to_string(),
+                status: PresenceStatus::Offline,
+                last_seen: *last_seen,
+            })
+            .ok_or(ChatError::UnknownUser)
+    }
+
+    /// Get presence of all announced users.
+    pub fn presences(&self) -> Vec<Presence> {
+        self.users
+            .values()
+            .filter(|user| user.announced)
+            .map(User::presence)
+            .collect()
+    }
+
+    /// Broadcast presence of user with given id, if it was announced.
+    fn emit_presence(&self, id: usize) {
+        if let Some(user) = self.users.get(&id).filter(|user| user.announced) {
+            let _ = self.presence_sender.send(user.presence());
+        }
+    }
+
+    /// Get room with given name.
+    pub fn room(&self, name: &str) -> Result<&Room, ChatError> {
+        self.rooms.get(name).ok_or(ChatError::UnknownRoom)
+    }
+
+    /// Get room with given name if user with given id is its member.
+    pub fn member_room(&self, id: usize, name: &str) -> Result<&Room, ChatError> {
+        let room = self.room(name)?;
+        if room.members.contains(&id) {
+            Ok(room)
+        } else {
+            Err(ChatError::NotMember)
+        }
+    }
+
+    /// Create new room and make user with given id join it.
+    pub fn create_room(&mut self, id: usize, name: &str) -> Result<(), ChatError> {
+        validate_room_name(name)?;
+        let user_name = self.user(id)?.name.clone();
+        if self.rooms.contains_key(name) {
+            return Err(ChatError::RoomExists);
+        }
+        self.rooms.insert(
+            name.to_string(),
+            Room::new(name.to_string(), self.capacities),
+        );
+        self.emit(ServerEvent::RoomCreated {
+            room: name.to_string(),
+            by: user_name,
+        });
+        self.join_room(id, name)
+    }
+
+    /// Set topic of room on behalf of user with given id (who has to be its member).
+    pub fn set_topic(&mut self, id: usize, name: &str, topic: &str) -> Result<(), ChatError> {
+        let topic = topic.trim();
+        if topic.chars().count() > MAX_TOPIC_LENGTH {
+            return Err(ChatError::TopicTooLong);
+        }
+        self.member_room(id, name)?;
+        let room = self.rooms.get_mut(name).unwrap();
+        if room.topic == topic {
+            return Ok(());
+        }
+        room.topic = topic.to_string();
+        self.emit(ServerEvent::TopicChanged {
+            room: name.to_string(),
+            topic: topic.to_string(),
+            by: self.user(id)?.name.clone(),
+        });
+        Ok(())
+    }
+
+    /// Make user with given id join room (does nothing if user already is a member).
+    pub fn join_room(&mut self, id: usize, name: &str) -> Result<(), ChatError> {
+        let user_name = self.user(id)?.name.clone();
+        let room = self.rooms.get_mut(name).ok_or(ChatError::UnknownRoom)?;
+        if room.members.insert(id) {
+            let _ = room.joined_sender.send((id, user_name));
+        }
+        Ok(())
+    }
+
+    /// Make user with given id leave room.
+    ///
+    /// Rooms other than default one are removed once last member leaves.
+    pub fn leave_room(&mut self, id: usize, name: &str) -> Result<(), ChatError> {
+        self.stop_typing(id, name);
+        let user_name = self.user(id)?.name.clone();
+        let room = self.rooms.get_mut(name).ok_or(ChatError::UnknownRoom)?;
+        if !room.members.remove(&id) {
+            return Err(ChatError::NotMember);
+        }
+        let _ = room.left_sender.send((id, user_name));
+        if room.members.is_empty() && name != DEFAULT_ROOM {
+            self.rooms.remove(name);
+        }
+        Ok(())
+    }
+
+    /// Store message sent by user with given id to room (optionally as a reply to message
+    /// with id `reply_to`) and broadcast it to room members.
+    pub fn message(
+        &mut self,
+        id: usize,
+        room: &str,
+        body: String,
+        reply_to: Option<u64>,
+    ) -> Result<(), ChatError> {
+        self.member_room(id, room)?;
+        if self.is_muted(&self.user(id)?.account) {
+            return Err(ChatError::Muted);
+        }
+        let reply_to = reply_to
+            .map(|message_id| self.thread_start(message_id, room))
+            .transpose()?;
+        let sender_name = self.user(id)?.name.clone();
+        self.stop_typing(id, room);
+        self.send(room, id, sender_name, body, reply_to);
+        self.user_active(id);
+        Ok(())
+    }
+
+    /// Show typing indicator of user with given id in room for [TYPING_TIMEOUT],
+    /// others are notified only if it wasn't shown already.
+    pub fn start_typing(&mut self, id: usize, room: &str) -> Result<(), ChatError> {
+        self.member_room(id, room)?;
+        let expires = Instant::now() + TYPING_TIMEOUT;
+        if self
+            .typing
+            .insert((id, room.to_string()), expires)
+            .is_none()
+        {
+            self.emit_typing(id, room, true);
+        }
+        Ok(())
+    }
+
+    /// Hide typing indicator of user with given id in room.
+    pub fn stop_typing(&mut self, id: usize, room: &str) {
+        if self.typing.remove(&(id, room.to_string())).is_some() {
+            self.emit_typing(id, room, false);
+        }
+    }
+
+    /// Hide typing indicators that weren't refreshed in time.
+    pub fn expire_typing(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<(usize, String)> = self
+            .typing
+            .iter()
+            .filter(|(_, expires)| **expires <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for (id, room) in expired {
+            self.stop_typing(id, &room);
+        }
+    }
+
+    /// Get typing indicators of other members of rooms user with given id is member of,
+    /// for all of them (including ones not typing), used to resynchronize lagging subscribers.
+    pub fn typing_snapshot(&self, id: usize) -> Vec<Typing> {
+        self.rooms
+            .values()
+            .filter(|room| room.members.contains(&id))
+            .flat_map(|room| {
+                room.members
+                    .iter()
+                    .filter(|member| **member != id)
+                    .filter_map(|member| {
+                        Some(Typing {
+                            room: room.name.clone(),
+                            user: self.users.get(member)?.name.clone(),
+                            typing: self.typing.contains_key(&(*member, room.name.clone())),
+                        })
+                    })
+            })
+            .collect()
+    }
+
+    fn emit_typing(&self, id: usize, room: &str, typing: bool) {
+        if let Some(user) = self.users.get(&id) {
+            let typing = Typing {
+                room: room.to_string(),
+                user: user.name.clone(),
+                typing,
+            };
+            let _ = self.typing_sender.send((id, typing));
+        }
+    }
+
+    /// Get id of first message of thread containing message with given id sent to room.
+    fn thread_start(&self, message_id: u64, room: &str) -> Result<u64, ChatError> {
+        match self.storage.message(message_id) {
+            Some(stored) if !stored.message.deleted && stored.message.room == room => {
+                Ok(stored.message.reply_to.unwrap_or(message_id))
+            }
+            _ => Err(ChatError::UnknownMessage),
+        }
+    }
+
+    /// Get thread containing message with given id: its first message followed by replies.
+    pub fn thread(&self, id: usize, message_id: u64) -> Result<Vec<ChatMessage>, ChatError> {
+        let message = &self
+            .storage
+            .message(message_id)
+            .ok_or(ChatError::UnknownMessage)?
+            .message;
+        self.member_room(id, &message.room)?;
+        let start = message.reply_to.unwrap_or(message_id);
+        let mut messages: Vec<ChatMessage> = self
+            .storage
+            .message(start)
+            .map(|stored| stored.message.clone())
+            .into_iter()
+            .collect();
+        messages.extend(self.storage.replies(start));
+        Ok(self.with_reactions(messages))
+    }
+
+    /// Change body of message with given id on behalf of user with given id.
+    pub fn edit_message(
+        &mut self,
+        id: usize,
+        message_id: u64,
+        body: String,
+    ) -> Result<(), ChatError> {
+        if self.is_muted(&self.user(id)?.account) {
+            return Err(ChatError::Muted);
+        }
+        self.revise_message(id, message_id, Some(body))
+    }
+
+    /// Delete message with given id on behalf of user with given id.
+    pub fn delete_message(&mut self, id: usize, message_id: u64) -> Result<(), ChatError> {
+        self.revise_message(id, message_id, None)
+    }
+
+    /// Get edit history of message with given id (moderators only).
+    pub fn revisions(&self, id: usize, message_id: u64) -> Result<Vec<Revision>, ChatError> {
+        if self.user(id)?.role < Role::Moderator {
+            return Err(ChatError::NotAuthorized);
+        }
+        if self.storage.message(message_id).is_none() {
+            return Err(ChatError::UnknownMessage);
+        }
+        Ok(self.storage.revisions(message_id))
+    }
+
+    /// Store revision of message and broadcast it to room members,
+    /// messages can be changed by their senders and moderators.
+    fn revise_message(
+        &mut self,
+        id: usize,
+        message_id: u64,
+        body: Option<String>,
+    ) -> Result<(), ChatError> {
+        let mut message = match self.storage.message(message_id) {
+            Some(stored) if !stored.message.deleted => {
+                let user = self.user(id)?;
+                if stored.account.to_lowercase() != user.account.to_lowercase()
+                    && user.role < Role::Moderator
+                {
+                    return Err(ChatError::NotAuthorized);
+                }
+                stored.message.clone()
+            }
+            _ => return Err(ChatError::UnknownMessage),
+        };
+        self.member_room(id, &message.room)?;
+        let revision = Revision {
+            message_id,
+            timestamp: Utc::now(),
+            editor: self.user(id)?.name.clone(),
+            previous_body: message.body.clone(),
+            body,
+        };
+        if let Err(error) = self.storage.revise(&revision) {
+            error!("Unable to store revision of message {message_id}: {error:#}.");
+        }
+        revision.apply(&mut message);
+        let event = if message.deleted {
+            MessageEvent::Deleted { id: message_id }
+        } else {
+            message.reactions = self.reactions(message_id);
+            MessageEvent::Edited(message.clone())
+        };
+        let _ = self.rooms[&message.room].update_sender.send(event);
+        Ok(())
+    }
+
+    /// Add reaction of user with given id to message with given id.
+    pub fn react(&mut self, id: usize, message_id: u64, emoji: String) -> Result<(), ChatError> {
+        validate_emoji(&emoji)?;
+        let (room, account) = self.reacting_user(id, message_id)?;
+        self.change_reaction(&room, message_id, emoji, account, true);
+        Ok(())
+    }
+
+    /// Remove reaction of user with given id to message with given id.
+    pub fn unreact(&mut self, id: usize, message_id: u64, emoji: &str) -> Result<(), ChatError> {
+        let (room, account) = self.reacting_user(id, message_id)?;
+        self.change_reaction(&room, message_id, emoji.to_string(), account, false);
+        Ok(())
+    }
+
+    /// Store added or removed reaction and notify room if reactions to message changed.
+    fn change_reaction(
+        &mut self,
+        room: &str,
+        message_id: u64,
+        emoji: String,
+        account: String,
+        added: bool,
+    ) {
+        let change = ReactionChange {
+            message_id,
+            timestamp: Utc::now(),
+            emoji,
+            account,
+            added,
+        };
+        match self.storage.react(&change) {
+            Ok(true) => self.emit_reactions(room, message_id),
+            Ok(false) => {}
+            Err(error) => error!("Unable to store reaction to message {message_id}: {error:#}."),
+        }
+    }
+
+    /// Get reactions to message with given id, ordered by emoji.
+    pub fn reactions(&self, message_id: u64) -> Vec<Reaction> {
+        self.storage.reactions(message_id)
+    }
+
+    /// Fill in reactions of messages.
+    pub fn with_reactions(&self, mut messages: Vec<ChatMessage>) -> Vec<ChatMessage> {
+        for message in &mut messages {
+            message.reactions = self.reactions(message.id);
+        }
+        messages
+    }
+
+    /// Get room of message with given id and lowercase account of user with given id
+    /// if user can react to it.
+    fn reacting_user(&self, id: usize, message_id: u64) -> Result<(String, String), ChatError> {
+        let room = match self.storage.message(message_id) {
+            Some(stored) if !stored.message.deleted => stored.message.room.clone(),
+            _ => return Err(ChatError::UnknownMessage),
+        };
+        self.member_room(id, &room)?;
+        let account = &self.user(id)?.account;
+        if self.is_muted(account) {
+            return Err(ChatError::Muted);
+        }
+        Ok((room, account.to_lowercase()))
+    }
+
+    fn emit_reactions(&self, room: &str, message_id: u64) {
+        let _ = self.rooms[room]
+            .update_sender
+            .send(MessageEvent::Reactions {
+                id: message_id,
+                reactions: self.reactions(message_id),
+            });
+    }
+
+    /// Send message from server to room.
+    pub fn announce(&mut self, room: &str, body: String) {
+        if self.rooms.contains_key(room) {
+            self.send(
+                room,
+                SERVER_USER_ID,
+                SERVER_USER_NAME.to_string(),
+                body,
+                None,
+            );
+        }
+    }
+
+    /// Send notice to all users on behalf of user with given id.
+    pub fn notice(&self, id: usize, message: &str) -> Result<(), ChatError> {
+        let user = self.user(id)?;
+        if user.role < Role::Admin {
+            return Err(ChatError::NotAuthorized);
+        }
+        self.emit(ServerEvent::Notice {
+            message: message.to_string(),
+            by: user.name.clone(),
+        });
+        Ok(())
+    }
+
+    /// Broadcast server event to all subscribers.
+    pub fn emit(&self, event: ServerEvent) {
+        let _ = self.events_sender.send(event);
+    }
+
+    /// Send message from server to all rooms user with given id is member of.
+    fn announce_to_user_rooms(&mut self, id: usize, body: String) {
+        for room in self.user_rooms(id) {
+            self.announce(&room, body.clone());
+        }
+    }
+
+    /// Store message and broadcast it to room members.
+    fn send(
+        &mut self,
+        room: &str,
+        sender_id: usize,
+        sender_name: String,
+        body: String,
+        reply_to: Option<u64>,
+    ) {
+        let message = ChatMessage {
+            id: self.next_message_id,
+            timestamp: Utc::now(),
+            room: room.to_string(),
+            sender_id,
+            sender_name,
+            body,
+            reply_to,
+            edited: None,
+            deleted: false,
+            reactions: vec![],
+        };
+        self.next_message_id += 1;
+        let account = self
+            .users
+            .get(&sender_id)
+            .map_or("", |user| user.account.as_str());
+        if let Err(error) = self.storage.append(&message, account) {
+            error!("Unable to store message: {error:#}.");
+        }
+        let _ = self.rooms[room].message_sender.send(message);
+    }
+
+    /// Get message history of room user with given id is member of.
+    pub fn history(
+        &self,
+        id: usize,
+        room: &str,
+        before: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<ChatMessage>, ChatError> {
+        self.member_room(id, room)?;
+        Ok(self.with_reactions(self.storage.history(room, before, limit)))
+    }
+
+    /// Get names of users in room.
+    pub fn room_members(&self, name: &str) -> Result<Vec<String>, ChatError> {
+        Ok(self
+            .room(name)?
+            .members
+            .iter()
+            .map(|id| self.users[id].name.clone())
+            .collect())
+    }
+
+    /// Get role of given account.
+    pub fn role(&self, account: &str) -> Role {
+        self.roles
+            .get(&account.to_lowercase())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Check if user with given account is muted.
+    pub fn is_muted(&self, account: &str) -> bool {
+        self.mutes
+            .get(&account.to_lowercase())
+            .is_some_and(|until| until.is_none_or(|until| until > Instant::now()))
+    }
+
+    /// Get id of user with given name if user with given id has at least `required` role
+    /// and higher role than that user.
+    fn moderated_user(&self, id: usize, name: &str, required: Role) -> Result<usize, ChatError> {
+        let role = self.user(id)?.role;
+        if role < required {
+            return Err(ChatError::NotAuthorized);
+        }
+        let target = self
+            .session_by_name(name)
+            .filter(|user| !user.kicked)
+            .ok_or(ChatError::UnknownUser)?;
+        if target.role >= role {
+            return Err(ChatError::NotAuthorized);
+        }
+        Ok(target.id)
+    }
+
+    /// Close transports of all connected users, their sessions are kept.
+    pub fn close_connections(&self) {
+        for user in self.users.values().filter(|user| user.connected) {
+            user.disconnect.notify_one();
+        }
+    }
+
+    /// Send event with reason to user with given id and close its transport,
+    /// so session can't be resumed.
+    ///
+    /// User is removed once its transport is closed, see [State::disconnect_user]
+    /// (right away if it is already disconnected).
+    fn disconnect(&mut self, id: usize, reason: ServerEvent) {
+        let Some(user) = self.users.get_mut(&id) else {
+            return;
+        };
+        user.kicked = true;
+        let _ = user.events_sender.send(reason);
+        if user.connected {
+            user.disconnect.notify_one();
+        } else {
+            self.remove_user(id);
+        }
+    }
+
+    /// Kick user with given name on behalf of user with given id
+    /// and return name of kicked user.
+    pub fn kick(&mut self, id: usize, name: &str, reason: &str) -> Result<String, ChatError> {
+        let target = self.moderated_user(id, name, Role::Moderator)?;
+        let target_name = self.users[&target].name.clone();
+        let moderator_name = self.user(id)?.name.clone();
+        let announcement = format!(
+            "{target_name} was kicked by {moderator_name}{}",
+            with_reason(reason)
+        );
+        self.announce_to_user_rooms(target, announcement);
+        let event = ServerEvent::Kicked {
+            by: moderator_name,
+            reason: reason.trim().to_string(),
+        };
+        self.disconnect(target, event);
+        Ok(target_name)
+    }
+
+    /// Ban account or address of user with given name on behalf of user with given id,
+    /// disconnect all users ban applies to and return their names.
+    pub fn ban(
+        &mut self,
+        id: usize,
+        name: &str,
+        scope: BanScope,
+        duration: Option<Duration>,
+        reason: &str,
+    ) -> Result<Vec<String>, ChatError> {
+        let target = self.moderated_user(id, name, Role::Admin)?;
+        let admin = self.user(id)?;
+        let target_user = &self.users[&target];
+        let target = match scope {
+            BanScope::Account => BanTarget::Account(target_user.account.to_lowercase()),
+            BanScope::Address => BanTarget::Address(target_user.address),
+        };
+        // bans ending too far in the future are permanent
+        let until = duration.and_then(|duration| {
+            Utc::now().checked_add_signed(chrono::Duration::from_std(duration).ok()?)
+        });
+        let duration = duration.filter(|_| until.is_some());
+        let ban = Ban {
+            target,
+            until,
+            reason: reason.to_string(),
+            by: admin.account.clone(),
+        };
+        // admins can't lock themselves out
+        if ban.applies_to(&admin.account, admin.address) {
+            return Err(ChatError::NotAuthorized);
+        }
+        let banned: Vec<usize> = self
+            .users
+            .values()
+            .filter(|user| ban.applies_to(&user.account, user.address))
+            .map(|user| user.id)
+            .collect();
+        let admin_name = admin.name.clone();
+        if let Err(error) = self.bans.add(ban) {
+            error!("Unable to store ban: {error:#}.");
+        }
+        let mut names = vec![];
+        for target in banned {
+            let target_name = self.users[&target].name.clone();
+            let announcement = format!(
+                "{target_name} was banned by {admin_name} {}{}",
+                format_duration(duration),
+                with_reason(reason)
+            );
+            self.announce_to_user_rooms(target, announcement);
+            let event = ServerEvent::Banned {
+                by: admin_name.clone(),
+                until,
+                reason: reason.trim().to_string(),
+            };
+            self.disconnect(target, event);
+            names.push(target_name);
+        }
+        Ok(names)
+    }
+
+    /// Lift ban of account with given name or given address on behalf of user with given id,
+    /// return `false` if target wasn't banned.
+    pub fn unban(&mut self, id: usize, target: &str) -> Result<bool, ChatError> {
+        if self.user(id)?.role < Role::Admin {
+            return Err(ChatError::NotAuthorized);
+        }
+        Ok(self
+            .bans
+            .remove(&BanTarget::parse(target))
+            .unwrap_or_else(|error| {
+                error!("Unable to store bans: {error:#}.");
+                true
+            }))
+    }
+
+    /// Mute user with given name on behalf of user with given id.
+    pub fn mute(
+        &mut self,
+        id: usize,
+        name: &str,
+        duration: Option<Duration>,
+    ) -> Result<(), ChatError> {
+        let target = self.moderated_user(id, name, Role::Moderator)?;
+        self.mutes
+            .retain(|_, until| until.is_none_or(|until| until > Instant::now()));
+        // mutes ending too far in the future last until unmuted
+        let until = duration.and_then(|duration| Instant::now().checked_add(duration));
+        let duration = duration.filter(|_| until.is_some());
+        self.mutes
+            .insert(self.users[&target].account.to_lowercase(), until);
+        let announcement = format!(
+            "{} was muted by {} {}",
+            self.users[&target].name,
+            self.user(id)?.name,
+            match duration {
+                Some(_) => format_duration(duration),
+                None => "until unmuted".to_string(),
+            }
+        );
+        self.announce_to_user_rooms(target, announcement);
+        Ok(())
+    }
+
+    /// Unmute user with given name on behalf of user with given id.
+    pub fn unmute(&mut self, id: usize, name: &str) -> Result<(), ChatError> {
+        let target = self.moderated_user(id, name, Role::Moderator)?;
+        let account = self.users[&target].account.to_lowercase();
+        if self.mutes.remove(&account).is_some() {
+            let announcement = format!(
+                "{} was unmuted by {}",
+                self.users[&target].name,
+                self.user(id)?.name
+            );
+            self.announce_to_user_rooms(target, announcement);
+        }
+        Ok(())
+    }
+}
+
+/// Format optional reason of moderation action to be appended to announcement.
+fn with_reason(reason: &str) -> String {
+    let reason = reason.trim();
+    if reason.is_empty() {
+        String::new()
+    } else {
+        format!(": {reason}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
+
+    use common::api::chat::{
+        BanScope, ChatError, MessageEvent, PresenceStatus, Reaction, Role, ServerEvent, Typing,
+        DEFAULT_ROOM, MAX_HISTORY_LIMIT, SERVER_USER_NAME,
+    };
+
+    use crate::{moderation::Bans, storage::MemoryStorage};
+
+    use super::{Capacities, State};
+
+    const ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn state() -> State {
+        let capacities = Capacities {
+            events: 10,
+            messages: 10,
+        };
+        State::new(
+            capacities,
+            Box::<MemoryStorage>::default(),
+            Duration::from_secs(30),
+            HashMap::from([
+                ("alice".to_string(), Role::Admin),
+                ("mod".to_string(), Role::Moderator),
+            ]),
+            Bans::default(),
+        )
+    }
+
+    #[test]
+    fn test_rename_user() {
+        let mut state = state();
+        let alice = state.add_user("Alice", ADDRESS).id;
+        let bob = state.add_user("Bob", ADDRESS).id;
+        let mut events = state.events_sender.subscribe();
+
+        let old_name = state.rename_user(alice, "Alicia".to_string()).unwrap();
+        assert_eq!(old_name, "Alice");
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ServerEvent::UserRenamed {
+                old_name,
+                new_name: "Alicia".to_string()
+            }
+        );
+        assert_eq!(
+            state.rename_user(bob, "alicia".to_string()),
+            Err(ChatError::NameTaken)
+        );
+        assert_eq!(
+            state.rename_user(bob, "System".to_string()),
+            Err(ChatError::ReservedName)
+        );
+        assert!(state.rename_user(alice, "ALICIA".to_string()).is_ok());
+    }
+
+    #[test]
+    fn test_rooms() {
+        let mut state = state();
+        let alice = state.add_user("Alice", ADDRESS).id;
+        let bob = state.add_user("Bob", ADDRESS).id;
+        assert_eq!(state.room_members(DEFAULT_ROOM).unwrap().len(), 2);
+        let mut events = state.events_sender.subscribe();
+
+        state.create_room(alice, "rust").unwrap();
+        assert_eq!(state.create_room(bob, "rust"), Err(ChatError::RoomExists));
+        assert_eq!(state.leave_room(bob, "rust"), Err(ChatError::NotMember));
+        assert_eq!(
+            state.set_topic(bob, "rust", "Rust"),
+            Err(ChatError::NotMember)
+        );
+        state.join_room(bob, "rust").unwrap();
+        state.set_topic(bob, "rust", " Rust ").unwrap();
+        assert_eq!(state.rooms["rust"].topic, "Rust");
+        assert_eq!(
+            std::iter::from_fn(|| events.try_recv().ok()).collect::<Vec<_>>(),
+            [
+                ServerEvent::RoomCreated {
+                    room: "rust".to_string(),
+                    by: "Alice".to_string()
+                },
+                ServerEvent::TopicChanged {
+                    room: "rust".to_string(),
+                    topic: "Rust".to_string(),
+                    by: "Bob".to_string()
+                }
+            ]
+        );
+        assert_eq!(state.room_members("rust").unwrap().len(), 2);
+
+        state.leave_room(alice, "rust").unwrap();
+        state.remove_user(bob);
+        assert_eq!(state.room("rust").unwrap_err(), ChatError::UnknownRoom);
+        assert_eq!(state.room_members(DEFAULT_ROOM).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_direct_message() {
+        let mut state = state();
+        let alice = state.add_user("Alice", ADDRESS).id;
+        let bob = state.add_user("Bob", ADDRESS).id;
+        let mut inbox = state.users[&bob].inbox_sender.subscribe();
+
+        state
+            .direct_message(alice, "bob", "Hi!".to_string())
+            .unwrap();
+        let (sender_name, message) = inbox.try_recv().unwrap();
+        assert_eq!(sender_name, state.users[&alice].name);
+        assert_eq!(message, "Hi!");
+
+        // messages to disconnected users would be lost
+        state.disconnect_user(bob);
+        assert_eq!(
+            state.direct_message(alice, "Bob", "Hi?".to_string()),
+            Err(ChatError::UnknownUser)
+        );
+        assert_eq!(
+            state.rename_user(alice, "BOB".to_string()),
+            Err(ChatError::NameTaken)
+        );
+        state.remove_user(bob);
+        assert_eq!(
+            state.direct_message(alice, "Bob", "Hi?".to_string()),
+            Err(ChatError::UnknownUser)
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let mut state = state();
+        let alice = state.add_user("Alice", ADDRESS).id;
+        let bob = state.add_user("Bob", ADDRESS).id;
+        state.create_room(alice, "rust").unwrap();
+        state
+            .message(alice, "rust", "Hello".to_string(), None)
+            .unwrap();
+        state
+            .message(alice, DEFAULT_ROOM, "Hi".to_string(), None)
+            .unwrap();
+
+        assert_eq!(
+            state.history(bob, "rust", None, 10),
+            Err(ChatError::NotMember)
+        );
+        state.join_room(bob, "rust").unwrap();
+        let history = state.history(bob, "rust", None, 10).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].body, "Hello");
+        assert_eq!(history[0].sender_id, alice);
+    }
+
+    #[test]
+    fn test_thread() {
+        let mut state = state();
+        let alice = state.add_user("Alice", ADDRESS).id;
+        let bob = state.add_user("Bob", ADDRESS).id;
+        state.create_room(alice, "rust").unwrap();
+        let send = |state: &mut State, id, room, reply_to| {
+            state
+                .message(id, room, "Hi".to_string(), reply_to)
+                .map(|()| state.storage.last_id())
+        };
+        let start = send(&mut state, alice, DEFAULT_ROOM, None).unwrap();
+        let reply = send(&mut state, bob, DEFAULT_ROOM, Some(start)).unwrap();
+        send(&mut state, alice, DEFAULT_ROOM, None).unwrap();
+        // reply to reply is added to thread of original message
+        let nested = send(&mut state, alice, DEFAULT_ROOM, Some(reply)).unwrap();
+        assert_eq!(
+            send(&mut state, alice, "rust", Some(start)),
+            Err(ChatError::UnknownMessage)
+        );
+
+        let thread = state.thread(bob, nested).unwrap();
+        let ids: Vec<u64> = thread.iter().map(|message| message.id).collect();
+        assert_eq!(ids, [start, reply, nested]);
+        assert_eq!(thread[2].reply_to, Some(start));
+        assert_eq!(state.thread(bob, 100), Err(ChatError::UnknownMessage));
+    }
+
+    #[test]
+    fn test_edit_message() {
+        let mut state = state();
+        let bob = state.add_user("Bob", ADDRESS).id;
+        let carol = state.add_user("Carol", ADDRESS).id;
+        let moderator = state.add_user("Mod", ADDRESS).id;
+        let mut updates = state.rooms[DEFAULT_ROOM].update_sender.subscribe();
+        state
+            .message(bob, DEFAULT_ROOM, "Hello".to_string(), None)
+            .unwrap();
+        let id = state.storage.last_id();
+
+        assert_eq!(
+            state.edit_message(carol, id, "Bye".to_string()),
+            Err(ChatError::NotAuthorized)
+        );
+        state.edit_message(bob, id, "Hi".to_string()).unwrap();
+        match updates.try_recv().unwrap() {
+            MessageEvent::Edited(message) => {
+                assert_eq!(message.body, "Hi");
+                assert!(message.edited.is_some());
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+        assert_eq!(state.revisions(bob, id), Err(ChatError::NotAuthorized));
+
+        state.delete_message(moderator, id).unwrap();
+        assert_eq!(updates.try_recv().unwrap(), MessageEvent::Deleted { id });
+        assert_eq!(
+            state.edit_message(bob, id, "Hello".to_string()),
+            Err(ChatError::UnknownMessage)
+        );
+        assert!(state.history(bob, DEFAULT_ROOM, None, 10).unwrap()[0].deleted);
+
+        let revisions = state.revisions(moderator, id).unwrap();
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].previous_body, "Hello");
+        assert_eq!(revisions[1].previous_body, "Hi");
+        assert_eq!(revisions[1].editor, "Mod");
+        assert_eq!(revisions[1].body, None);
+    }
+
+    #[test]
+    fn test_reactions() {
+        let mut state = state();
+        let alice = state.add_user("Alice", ADDRESS).id;
+        let bob = state.add_user("Bob", ADDRESS).id;
+        let mut updates = state.rooms[DEFAULT_ROOM].update_sender.subscribe();
+        state
+            .message(alice, DEFAULT_ROOM, "Hello".to_string(), None)
+            .unwrap();
+        let id = state.storage.last_id();
+        let thumbs_up = |count| Reaction {
+            emoji: "👍".to_string(),
+            count,
+        };
+
+        state.react(alice, id, "👍".to_string()).unwrap();
+        state.react(bob, id, "👍".to_string()).unwrap();
+        // reacting again doesn't change count
+        state.react(bob, id, "👍".to_string()).unwrap();
+        assert_eq!(
+            state.react(bob, id, "+1".to_string()),
+            Err(ChatError::InvalidEmoji)
+        );
+        assert_eq!(
+            state.react(bob, id + 1, "👍".to_string()),
+            Err(ChatError::UnknownMessage)
+        );
+        let history = state.history(bob, DEFAULT_ROOM, None, 10).unwrap();
+        assert_eq!(history[0].reactions, [thumbs_up(2)]);
+
+        state.unreact(alice, id, "👍").unwrap();
+        state.unreact(alice, id, "👍").unwrap();
+        let events: Vec<MessageEvent> = std::iter::from_fn(|| updates.try_recv().ok()).collect();
+        assert_eq!(
+            events,
+            [
+                MessageEvent::Reactions {
+                    id,
+                    reactions: vec![thumbs_up(1)]
+                },
+                MessageEvent::Reactions {
+                    id,
+                    reactions: vec![thumbs_up(2)]
+                },
+                MessageEvent::Reactions {
+                    id,
+                    reactions: vec![thumbs_up(1)]
+                },
+            ]
+        );
+
+        state.delete_message(alice, id).unwrap();
+        assert!(state.reactions(id).is_empty());
+    }
+
+    #[test]
+    fn test_resume_session() {
+        let mut state = state();
+        let alice = state.add_user("Alice", ADDRESS).id;
+        let token = state.users[&alice].token.clone();
+        let connection = state.disconnect_user(alice).unwrap();
+        let bob = state.add_user("Bob", ADDRESS).id;
+        state
+            .message(bob, DEFAULT_ROOM, "Hi".to_string(), None)
+            .unwrap();
+
+        let new_alice = state.add_user("Alice", ADDRESS).id;
+        assert_eq!(state.users[&new_alice].name, "Alice-2");
+        assert_eq!(
+            state.resume_session(new_alice, "invalid"),
+            Err(ChatError::InvalidSessionToken)
+        );
+        assert_eq!(state.resume_session(new_alice, &token), Ok(alice));
+        assert!(!state.users.contains_key(&new_alice));
+        assert_eq!(state.missed_messages(alice, 0).len(), 1);
+        for _ in 0..MAX_HISTORY_LIMIT {
+            state
+                .message(bob, DEFAULT_ROOM, "Hi".to_string(), None)
+                .unwrap();
+        }
+        let missed = state.missed_messages(alice, 0);
+        assert_eq!(missed.len(), MAX_HISTORY_LIMIT + 1);
+        assert_eq!(
+            missed[0],
+            (DEFAULT_ROOM.to_string(), MessageEvent::Gap { missed: 1 })
+        );
+        assert!(matches!(&missed[1].1, MessageEvent::Message(message) if message.id == 2));
+
+        // session was resumed, so it doesn't expire
+        assert!(state.expire_session(alice, connection).is_none());
+        let other_alice = state.add_user("Alice", ADDRESS).id;
+        assert_eq!(
+            state.resume_session(other_alice, &token),
+            Err(ChatError::SessionInUse)
+        );
+        assert_eq!(
+            state.resume_session(bob, &token),
+            Err(ChatError::InvalidSessionToken)
+        );
+    }
+
+    #[test]
+    fn test_presence() {
+        let mut state = state();
+        let mut events = state.events_sender.subscribe();
+        let mut presence = state.presence_sender.subscribe();
+        let alice = state.add_user("Alice", ADDRESS).id;
+        let bob = state.add_user("Bob", ADDRESS).id;
+        state.announce_user(alice);
+        state.announce_user(alice);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ServerEvent::UserJoined {
+                user: "Alice".to_string()
+            }
+        );
+        assert!(events.try_recv().is_err());
+        assert_eq!(state.presences().len(), 1);
+
+        assert_eq!(
+            state.set_status(alice, PresenceStatus::Idle),
+            Err(ChatError::InvalidStatus)
+        );
+        state.set_status(alice, PresenceStatus::Away).unwrap();
+        assert_eq!(presence.try_recv().unwrap().status, PresenceStatus::Away);
+
+        state.announce_user(bob);
+        state.users.get_mut(&bob).unwrap().last_active -= chrono::Duration::minutes(10);
+        state.detect_idle(Duration::from_secs(60));
+        let update = presence.try_recv().unwrap();
+        assert_eq!(
+            (update.user.as_str(), update.status),
+            ("Bob", PresenceStatus::Idle)
+        );
+        // away users are not marked as idle
+        assert!(presence.try_recv().is_err());
+        state.user_active(bob);
+        assert_eq!(presence.try_recv().unwrap().status, PresenceStatus::Online);
+
+        let connection = state.disconnect_user(bob).unwrap();
+        assert_eq!(presence.try_recv().unwrap().status, PresenceStatus::Offline);
+        state.expire_session(bob, connection).unwrap();
+        let bob_presence = state.user_presence("bob").unwrap();
+        assert_eq!(bob_presence.status, PresenceStatus::Offline);
+        assert_eq!(state.user_presence("Carol"), Err(ChatError::UnknownUser));
+    }
+
+    #[test]
+    fn test_typing() {
+        let mut state = state();
+        let alice = state.add_user("Alice", ADDRESS).id;
+        let bob = state.add_user("Bob", ADDRESS).id;
+        let mut typing = state.typing_sender.subscribe();
+        assert_eq!(
+            state.start_typing(alice, "rust"),
+            Err(ChatError::UnknownRoom)
+        );
+
+        state.start_typing(alice, DEFAULT_ROOM).unwrap();
+        state.start_typing(alice, DEFAULT_ROOM).unwrap();
+        let (id, update) = typing.try_recv().unwrap();
+        assert_eq!(
+            (id, update.user.as_str(), update.typing),
+            (alice, "Alice", true)
+        );
+        assert!(typing.try_recv().is_err());
+        state
+            .message(alice, DEFAULT_ROOM, "Hi".to_string(), None)
+            .unwrap();
+        assert!(!typing.try_recv().unwrap().1.typing);
+
+        state.start_typing(bob, DEFAULT_ROOM).unwrap();
+        typing.try_recv().unwrap();
+        state.expire_typing();
+        assert!(typing.try_recv().is_err());
+        for expires in state.typing.values_mut() {
+            *expires = Instant::now();
+        }
+        state.expire_typing();
+        assert_eq!(typing.try_recv().unwrap(), (bob, typing_stopped("Bob")));
+
+        state.start_typing(bob, DEFAULT_ROOM).unwrap();
+        typing.try_recv().unwrap();
+        assert_eq!(
+            state.typing_snapshot(alice),
+            vec![Typing {
+                typing: true,
+                ..typing_stopped("Bob")
+            }]
+        );
+        assert_eq!(state.typing_snapshot(bob), vec![typing_stopped("Alice")]);
+        state.disconnect_user(bob);
+        assert_eq!(typing.try_recv().unwrap(), (bob, typing_stopped("Bob")));
+    }
+
+    fn typing_stopped(user: &str) -> Typing {
+        Typing {
+            room: DEFAULT_ROOM.to_string(),
+            user: user.to_string(),
+            typing: false,
+        }
+    }
+
+    #[test]
+    fn test_moderation() {
+        let mut state = state();
+        let alice = state.add_user("Alice", ADDRESS).id;
+        let moderator = state.add_user("Mod", ADDRESS).id;
+        let bob = state
+            .add_user("Bob", IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+            .id;
+        let mut messages = state.rooms[DEFAULT_ROOM].message_sender.subscribe();
+
+        assert_eq!(
+            state.kick(bob, "Mod", "").unwrap_err(),
+            ChatError::NotAuthorized
+        );
+        assert_eq!(
+            state.kick(moderator, "Alice", "").unwrap_err(),
+            ChatError::NotAuthorized
+        );
+        assert_eq!(
+            state
+                .ban(moderator, "Bob", BanScope::Account, None, "")
+                .unwrap_err(),
+            ChatError::NotAuthorized
+        );
+
+        state.mute(moderator, "bob", None).unwrap();
+        assert_eq!(
+            state.message(bob, DEFAULT_ROOM, "Hi".to_string(), None),
+            Err(ChatError::Muted)
+        );
+        state.unmute(moderator, "Bob").unwrap();
+        state
+            .message(bob, DEFAULT_ROOM, "Hi".to_string(), None)
+            .unwrap();
+
+        let bob_address = state.users[&bob].address;
+        let bob_token = state.users[&bob].token.clone();
+        let mut bob_events = state.users[&bob].events_sender.subscribe();
+        let banned = state
+            .ban(alice, "Bob", BanScope::Address, None, "spam")
+            .unwrap();
+        assert_eq!(banned, ["Bob"]);
+        assert!(state.bans.find("Bob", bob_address).is_some());
+        assert!(state.bans.find("Bob", ADDRESS).is_none());
+        assert_eq!(
+            bob_events.try_recv().unwrap(),
+            ServerEvent::Banned {
+                by: "Alice".to_string(),
+                until: None,
+                reason: "spam".to_string()
+            }
+        );
+        // banned user is removed once its transport is closed, session can't be resumed
+        assert!(state.users[&bob].kicked);
+        let other_bob = state.add_user("Bob", ADDRESS).id;
+        assert_eq!(
+            state.resume_session(other_bob, &bob_token),
+            Err(ChatError::InvalidSessionToken)
+        );
+        assert!(state.disconnect_user(bob).is_none());
+        assert!(!state.users.contains_key(&bob));
+        assert_eq!(
+            state.direct_message(bob, "Alice", "Hi".to_string()),
+            Err(ChatError::SessionClosed)
+        );
+
+        let announcements: Vec<String> = std::iter::from_fn(|| messages.try_recv().ok())
+            .filter(|message| message.sender_name == SERVER_USER_NAME)
+            .map(|message| message.body)
+            .collect();
+        assert_eq!(
+            announcements,
+            [
+                "Bob was muted by Mod until unmuted",
+                "Bob was unmuted by Mod",
+                "Bob was banned by Alice permanently: spam"
+            ]
+        );
+
+        // admin can't ban own address
+        let moderator_name = state.users[&moderator].name.clone();
+        assert_eq!(
+            state
+                .ban(alice, &moderator_name, BanScope::Address, None, "")
+                .unwrap_err(),
+            ChatError::NotAuthorized
+        );
+        assert_eq!(state.kick(alice, &moderator_name, ""), Ok(moderator_name));
+        assert_eq!(
+            state.kick(alice, "Mod", "").unwrap_err(),
+            ChatError::UnknownUser
+        );
+        state.disconnect_user(moderator);
+        assert!(!state.users.contains_key(&moderator));
+
+        // disconnected users are removed right away
+        let connection = state.disconnect_user(other_bob).unwrap();
+        state.kick(alice, "Bob-2", "").unwrap();
+        assert!(!state.users.contains_key(&other_bob));
+        assert!(state.expire_session(other_bob, connection).is_none());
+    }
+
+    #[test]
+    fn test_oversized_durations() {
+        let mut state = state();
+        let alice = state.add_user("Alice", ADDRESS).id;
+        let bob = state.add_user("Bob", ADDRESS).id;
+        state.add_user("Carol", ADDRESS);
+        let mut messages = state.rooms[DEFAULT_ROOM].message_sender.subscribe();
+        // overflows `Instant` and `DateTime` respectively
+        let days = |days: u64| Some(Duration::from_secs(days * 86400));
+
+        state.mute(alice, "Bob", days(110_000_000_000_000)).unwrap();
+        assert_eq!(
+            state.message(bob, DEFAULT_ROOM, "Hi".to_string(), None),
+            Err(ChatError::Muted)
+        );
+        let banned = state
+            .ban(alice, "Carol", BanScope::Account, days(100_000_000), "")
+            .unwrap();
+        assert!(state.bans.find("Carol", ADDRESS).unwrap().until.is_none());
+
+        let announcements: Vec<String> = std::iter::from_fn(|| messages.try_recv().ok())
+            .map(|message| message.body)
+            .collect();
+        assert_eq!(
+            announcements,
+            [
+                "Bob was muted by Alice until unmuted",
+                "Carol was banned by Alice permanently"
+            ]
+        );
+        assert_eq!(banned.len(), 1);
+    }
 }
@@ -0,0 +1,535 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Debug,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use common::api::chat::{ChatMessage, Reaction, Revision};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Message with account of its sender.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredMessage {
+    #[serde(flatten)]
+    pub message: ChatMessage,
+
+    /// Account sender authenticated with (empty for messages sent by server).
+    #[serde(default)]
+    pub account: String,
+}
+
+/// Reaction of account to message that was added or removed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionChange {
+    pub message_id: u64,
+    pub timestamp: DateTime<Utc>,
+    pub emoji: String,
+    /// Lowercase name of account that reacted.
+    pub account: String,
+    /// Whether reaction was added (or removed).
+    pub added: bool,
+}
+
+/// Line of history file.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Message(StoredMessage),
+    Revision(Revision),
+    Reaction(ReactionChange),
+}
+
+/// Persistent message storage.
+///
+/// Messages are never removed, edits and deletions are stored as [Revision]s
+/// and reactions as [ReactionChange]s.
+pub trait Storage: Debug + Send + Sync {
+    /// Id of last stored message (0 if storage is empty).
+    fn last_id(&self) -> u64;
+
+    /// Store new message sent by user authenticated with given account.
+    fn append(&mut self, message: &ChatMessage, account: &str) -> Result<()>;
+
+    /// Get message with given id.
+    fn message(&self, id: u64) -> Option<&StoredMessage>;
+
+    /// Apply edit or deletion to stored message and keep it in message's edit history.
+    fn revise(&mut self, revision: &Revision) -> Result<()>;
+
+    /// Get edit history of message with given id, oldest first.
+    fn revisions(&self, id: u64) -> Vec<Revision>;
+
+    /// Add or remove reaction to message that wasn't deleted,
+    /// returns whether reactions to message changed (only changes are stored).
+    fn react(&mut self, change: &ReactionChange) -> Result<bool>;
+
+    /// Get reactions to message with given id, ordered by emoji.
+    fn reactions(&self, id: u64) -> Vec<Reaction>;
+
+    /// Get messages replying to message with given id, oldest first.
+    fn replies(&self, id: u64) -> Vec<ChatMessage>;
+
+    /// Get at most `limit` latest messages sent to room with ids lower than `before`
+    /// (or latest messages if `before` is `None`), oldest first.
+    fn history(&self, room: &str, before: Option<u64>, limit: usize) -> Vec<ChatMessage>;
+
+    /// Get at most `limit` earliest messages sent to room with ids greater than `after`,
+    /// oldest first.
+    fn after(&self, room: &str, after: u64, limit: usize) -> Vec<ChatMessage>;
+
+    /// Check if storage is reachable and can accept new messages.
+    fn check(&self) -> Result<()>;
+
+    /// Make sure all stored messages are persisted.
+    fn flush(&mut self) -> Result<()>;
+}
+
+/// Storage keeping messages only in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    messages: Vec<StoredMessage>,
+    revisions: Vec<Revision>,
+    /// Accounts that reacted to messages, by message id and emoji.
+    reactions: HashMap<u64, BTreeMap<String, HashSet<String>>>,
+}
+
+impl MemoryStorage {
+    fn index(&self, id: u64) -> Option<usize> {
+        self.messages
+            .binary_search_by_key(&id, |stored| stored.message.id)
+            .ok()
+    }
+
+    /// Check if applying reaction change would change reactions to message.
+    fn changes_reactions(&self, change: &ReactionChange) -> Result<bool> {
+        match self.message(change.message_id) {
+            Some(stored) if !stored.message.deleted => {}
+            _ => bail!("message {} doesn't exist", change.message_id),
+        }
+        let reacted = self
+            .reactions
+            .get(&change.message_id)
+            .and_then(|reactions| reactions.get(&change.emoji))
+            .is_some_and(|accounts| accounts.contains(&change.account));
+        Ok(reacted != change.added)
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn last_id(&self) -> u64 {
+        self.messages.last().map_or(0, |stored| stored.message.id)
+    }
+
+    fn append(&mut self, message: &ChatMessage, account: &str) -> Result<()> {
+        self.messages.push(StoredMessage {
+            message: message.clone(),
+            account: account.to_string(),
+        });
+        Ok(())
+    }
+
+    fn message(&self, id: u64) -> Option<&StoredMessage> {
+        self.index(id).map(|index| &self.messages[index])
+    }
+
+    fn revise(&mut self, revision: &Revision) -> Result<()> {
+        let index = self
+            .index(revision.message_id)
+            .ok_or_else(|| anyhow!("message {} doesn't exist", revision.message_id))?;
+        revision.apply(&mut self.messages[index].message);
+        if self.messages[index].message.deleted {
+            self.reactions.remove(&revision.message_id);
+        }
+        self.revisions.push(revision.clone());
+        Ok(())
+    }
+
+    fn revisions(&self, id: u64) -> Vec<Revision> {
+        self.revisions
+            .iter()
+            .filter(|revision| revision.message_id == id)
+            .cloned()
+            .collect()
+    }
+
+    fn react(&mut self, change: &ReactionChange) -> Result<bool> {
+        if !self.changes_reactions(change)? {
+            return Ok(false);
+        }
+        let reactions = self.reactions.entry(change.message_id).or_default();
+        let accounts = reactions.entry(change.emoji.clone()).or_default();
+        if change.added {
+            accounts.insert(change.account.clone());
+        } else {
+            accounts.remove(&change.account);
+            reactions.retain(|_, accounts| !accounts.is_empty());
+            if reactions.is_empty() {
+                self.reactions.remove(&change.message_id);
+            }
+        }
+        Ok(true)
+    }
+
+    fn reactions(&self, id: u64) -> Vec<Reaction> {
+        self.reactions
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .map(|(emoji, accounts)| Reaction {
+                emoji: emoji.clone(),
+                count: accounts.len(),
+            })
+            .collect()
+    }
+
+    fn replies(&self, id: u64) -> Vec<ChatMessage> {
+        let start = self
+            .messages
+            .partition_point(|stored| stored.message.id <= id);
+        self.messages[start..]
+            .iter()
+            .map(|stored| &stored.message)
+            .filter(|message| message.reply_to == Some(id))
+            .cloned()
+            .collect()
+    }
+
+    fn history(&self, room: &str, before: Option<u64>, limit: usize) -> Vec<ChatMessage> {
+        let mut messages: Vec<ChatMessage> = self
+            .messages
+            .iter()
+            .rev()
+            .map(|stored| &stored.message)
+            .filter(|message| before.is_none_or(|before| message.id < before))
+            .filter(|message| message.room == room)
+            .take(limit)
+            .cloned()
+            .collect();
+        messages.reverse();
+        messages
+    }
+
+    fn after(&self, room: &str, after: u64, limit: usize) -> Vec<ChatMessage> {
+        let start = self
+            .messages
+            .partition_point(|stored| stored.message.id <= after);
+        self.messages[start..]
+            .iter()
+            .map(|stored| &stored.message)
+            .filter(|message| message.room == room)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    fn check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Storage appending messages, their revisions and reactions to a file (one JSON object per line).
+///
+/// Whole file is loaded into memory on startup, incomplete last record
+/// (left by crash while writing) is removed.
+#[derive(Debug)]
+pub struct LogStorage {
+    memory: MemoryStorage,
+    writer: BufWriter<File>,
+    path: PathBuf,
+}
+
+impl LogStorage {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("unable to open history file {path:?}"))?;
+
+        let mut memory = MemoryStorage::default();
+        let mut reader = BufReader::new(&file);
+        let mut line = vec![];
+        // length of complete records read so far
+        let mut length = 0;
+        for number in 1.. {
+            line.clear();
+            let read = reader
+                .read_until(b'\n', &mut line)
+                .with_context(|| format!("unable to read history file {path:?}"))?;
+            if read == 0 {
+                break;
+            }
+            if !line.ends_with(b"\n") {
+                warn!("Removing incomplete record at line {number} of history file {path:?}.");
+                file.set_len(length)
+                    .with_context(|| format!("unable to truncate history file {path:?}"))?;
+                break;
+            }
+            length += read as u64;
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            let invalid = || format!("invalid message in history file {path:?} at line {number}");
+            match serde_json::from_slice(&line).with_context(invalid)? {
+                Record::Message(stored) => memory.messages.push(stored),
+                Record::Revision(revision) => memory.revise(&revision).with_context(invalid)?,
+                Record::Reaction(change) => {
+                    memory.react(&change).with_context(invalid)?;
+                }
+            }
+        }
+
+        Ok(LogStorage {
+            memory,
+            writer: BufWriter::new(file),
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl LogStorage {
+    fn write(&mut self, record: &impl Serialize) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Storage for LogStorage {
+    fn last_id(&self) -> u64 {
+        self.memory.last_id()
+    }
+
+    fn append(&mut self, message: &ChatMessage, account: &str) -> Result<()> {
+        let stored = StoredMessage {
+            message: message.clone(),
+            account: account.to_string(),
+        };
+        self.write(&stored)?;
+        self.memory.messages.push(stored);
+        Ok(())
+    }
+
+    fn message(&self, id: u64) -> Option<&StoredMessage> {
+        self.memory.message(id)
+    }
+
+    fn revise(&mut self, revision: &Revision) -> Result<()> {
+        if self.memory.message(revision.message_id).is_none() {
+            bail!("message {} doesn't exist", revision.message_id);
+        }
+        self.write(revision)?;
+        self.memory.revise(revision)
+    }
+
+    fn revisions(&self, id: u64) -> Vec<Revision> {
+        self.memory.revisions(id)
+    }
+
+    fn react(&mut self, change: &ReactionChange) -> Result<bool> {
+        if !self.memory.changes_reactions(change)? {
+            return Ok(false);
+        }
+        self.write(change)?;
+        self.memory.react(change)
+    }
+
+    fn reactions(&self, id: u64) -> Vec<Reaction> {
+        self.memory.reactions(id)
+    }
+
+    fn replies(&self, id: u64) -> Vec<ChatMessage> {
+        self.memory.replies(id)
+    }
+
+    fn history(&self, room: &str, before: Option<u64>, limit: usize) -> Vec<ChatMessage> {
+        self.memory.history(room, before, limit)
+    }
+
+    fn after(&self, room: &str, after: u64, limit: usize) -> Vec<ChatMessage> {
+        self.memory.after(room, after, limit)
+    }
+
+    fn check(&self) -> Result<()> {
+        // messages would be appended to a removed file
+        let metadata = fs::metadata(&self.path)
+            .with_context(|| format!("history file {:?} is not accessible", self.path))?;
+        if metadata.permissions().readonly() {
+            bail!("history file {:?} is read-only", self.path);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.writer
+            .get_ref()
+            .sync_all()
+            .with_context(|| format!("unable to sync history file {:?}", self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env::temp_dir,
+        fs::{remove_file, OpenOptions},
+        io::Write,
+        process,
+    };
+
+    use chrono::Utc;
+    use common::api::chat::{ChatMessage, Reaction, Revision};
+
+    use super::{LogStorage, MemoryStorage, ReactionChange, Storage, StoredMessage};
+
+    fn message(storage: &dyn Storage, room: &str, body: &str) -> ChatMessage {
+        ChatMessage {
+            id: storage.last_id() + 1,
+            timestamp: Utc::now(),
+            room: room.to_string(),
+            sender_id: 1,
+            sender_name: "Alice".to_string(),
+            body: body.to_string(),
+            reply_to: None,
+            edited: None,
+            deleted: false,
+            reactions: vec![],
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let mut storage = MemoryStorage::default();
+        for i in 0..5 {
+            for room in ["general", "rust"] {
+                let message = message(&storage, room, &i.to_string());
+                storage.append(&message, "alice").unwrap();
+            }
+        }
+
+        let history = storage.history("general", None, 2);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].body, "3");
+        assert_eq!(history[1].body, "4");
+
+        let history = storage.history("general", Some(history[0].id), 10);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[2].body, "2");
+        assert!(history.iter().all(|message| message.room == "general"));
+
+        let after = storage.after("rust", history[0].id, 2);
+        assert_eq!(after.len(), 2);
+        assert_eq!(after[0].body, "0");
+        assert_eq!(after[1].body, "1");
+    }
+
+    #[test]
+    fn test_log_storage() {
+        let path = temp_dir().join(format!("history-test-{}.jsonl", process::id()));
+        {
+            let mut storage = LogStorage::open(&path).unwrap();
+            let hello = message(&storage, "general", "Hello");
+            storage.append(&hello, "alice").unwrap();
+            let hi = message(&storage, "general", "Hi");
+            storage.append(&hi, "alice").unwrap();
+            let revision = |body: Option<&str>, previous_body: &str| Revision {
+                message_id: hi.id,
+                timestamp: Utc::now(),
+                editor: "Alice".to_string(),
+                previous_body: previous_body.to_string(),
+                body: body.map(str::to_string),
+            };
+            let reaction = |message_id, account: &str, added| ReactionChange {
+                message_id,
+                timestamp: Utc::now(),
+                emoji: "👍".to_string(),
+                account: account.to_string(),
+                added,
+            };
+            for account in ["alice", "bob"] {
+                assert!(storage.react(&reaction(hello.id, account, true)).unwrap());
+            }
+            assert!(!storage.react(&reaction(hello.id, "bob", true)).unwrap());
+            assert!(storage.react(&reaction(hello.id, "alice", false)).unwrap());
+            assert!(storage.react(&reaction(hi.id, "alice", true)).unwrap());
+            storage.revise(&revision(Some("Hey"), "Hi")).unwrap();
+            storage.revise(&revision(None, "Hey")).unwrap();
+            // deleted message can't be reacted to
+            assert!(storage.react(&reaction(hi.id, "bob", true)).is_err());
+            assert!(storage
+                .revise(&Revision {
+                    message_id: 3,
+                    ..revision(None, "")
+                })
+                .is_err());
+        }
+        let storage = LogStorage::open(&path).unwrap();
+        let history = storage.history("general", None, 10);
+        assert!(storage.check().is_ok());
+        remove_file(&path).unwrap();
+        assert!(storage.check().is_err());
+
+        assert_eq!(storage.last_id(), 2);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].body, "");
+        assert!(history[1].deleted);
+        assert!(history[1].edited.is_some());
+        assert_eq!(storage.message(2).unwrap().account, "alice");
+        let revisions = storage.revisions(2);
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].body.as_deref(), Some("Hey"));
+        assert_eq!(revisions[1].previous_body, "Hey");
+        assert!(storage.revisions(1).is_empty());
+        assert_eq!(
+            storage.reactions(1),
+            [Reaction {
+                emoji: "👍".to_string(),
+                count: 1
+            }]
+        );
+        assert!(storage.reactions(2).is_empty());
+    }
+
+    #[test]
+    fn test_log_storage_incomplete_record() {
+        let path = temp_dir().join(format!("history-incomplete-test-{}.jsonl", process::id()));
+        {
+            let mut storage = LogStorage::open(&path).unwrap();
+            let hello = message(&storage, "general", "Hello");
+            storage.append(&hello, "alice").unwrap();
+        }
+        let record = serde_json::to_string(&StoredMessage {
+            message: message(&MemoryStorage::default(), "general", "Hi"),
+            account: "alice".to_string(),
+        })
+        .unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&record.as_bytes()[..record.len() / 2])
+            .unwrap();
+        drop(file);
+
+        {
+            let mut storage = LogStorage::open(&path).unwrap();
+            assert_eq!(storage.last_id(), 1);
+            let bye = message(&storage, "general", "Bye");
+            storage.append(&bye, "alice").unwrap();
+        }
+        let storage = LogStorage::open(&path).unwrap();
+        remove_file(&path).unwrap();
+        let history = storage.history("general", None, 10);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].body, "Bye");
+    }
+}
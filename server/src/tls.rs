@@ -0,0 +1,48 @@
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use tracing::warn;
+
+use crate::config::Tls;
+
+/// Names self-signed certificate is valid for.
+const SELF_SIGNED_NAMES: &[&str] = &["localhost", "127.0.0.1", "::1"];
+
+/// Generate self-signed certificate and key if enabled in settings and files don't exist yet.
+pub fn ensure_certificate(tls: &Tls) -> Result<()> {
+    if !tls.self_signed || (tls.cert.is_file() && tls.key.is_file()) {
+        return Ok(());
+    }
+
+    warn!(
+        "Generating self-signed certificate {:?} (for local development only).",
+        tls.cert
+    );
+    let names: Vec<String> = SELF_SIGNED_NAMES.iter().map(ToString::to_string).collect();
+    let certificate = rcgen::generate_simple_self_signed(names)
+        .context("unable to generate self-signed certificate")?;
+    fs::write(&tls.cert, certificate.serialize_pem()?)
+        .with_context(|| format!("unable to write certificate file {:?}", tls.cert))?;
+    write_private_key(&tls.key, certificate.serialize_private_key_pem().as_bytes())
+        .with_context(|| format!("unable to write key file {:?}", tls.key))?;
+    Ok(())
+}
+
+/// Write private key to file readable only by its owner (on unix).
+fn write_private_key(path: &Path, key: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path)?;
+    // mode is only applied to newly created files
+    #[cfg(unix)]
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(key)
+}